@@ -4,9 +4,21 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use regex::Regex;
+
 use super::defaults;
+use crate::probe::http::{HTTPAuth, HTTPBodyJSONMatch, HTTPMethod, HTTPStatusRange};
 use crate::probe::mode::Mode;
 use crate::probe::replica::ReplicaURL;
+use crate::probe::report::ReportTargetMode;
+use crate::probe::script::{ScriptConvention, ScriptEntry, ScriptOutputMode};
+use crate::probe::spool::SpoolReplay;
+use crate::probe::status::Status;
+use crate::probe::udp::UDPHexBytes;
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -20,12 +32,37 @@ pub struct Config {
 pub struct ConfigServer {
     #[serde(default = "defaults::server_log_level")]
     pub log_level: String,
+
+    pub inet: Option<SocketAddr>,
 }
 
 #[derive(Deserialize)]
 pub struct ConfigReport {
+    pub endpoint: Option<String>,
+    pub token: Option<String>,
+
+    #[serde(default)]
+    pub target: Vec<ConfigReportTarget>,
+
+    pub spool_path: Option<PathBuf>,
+
+    #[serde(default = "defaults::report_spool_max_entries")]
+    pub spool_max_entries: usize,
+
+    #[serde(default = "defaults::report_spool_max_age")]
+    pub spool_max_age: u64,
+
+    #[serde(default = "defaults::report_spool_replay")]
+    pub spool_replay: SpoolReplay,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigReportTarget {
     pub endpoint: String,
     pub token: String,
+
+    #[serde(default = "defaults::report_target_mode")]
+    pub mode: ReportTargetMode,
 }
 
 #[derive(Deserialize)]
@@ -33,6 +70,9 @@ pub struct ConfigMetrics {
     #[serde(default = "defaults::metrics_interval")]
     pub interval: u64,
 
+    #[serde(default = "defaults::metrics_max_concurrency")]
+    pub max_concurrency: u16,
+
     #[serde(default = "defaults::metrics_poll_retry")]
     pub poll_retry: u8,
 
@@ -46,11 +86,21 @@ pub struct ConfigMetrics {
 #[derive(Deserialize)]
 pub struct ConfigProbe {
     pub service: Vec<ConfigProbeService>,
+    pub tls_ca_file: Option<PathBuf>,
+    pub tls_cert_file: Option<PathBuf>,
+    pub tls_key_file: Option<PathBuf>,
+
+    #[serde(default = "defaults::probe_tls_verify")]
+    pub tls_verify: bool,
+
+    #[serde(default = "defaults::probe_script_timeout")]
+    pub script_timeout: u64,
 }
 
 #[derive(Deserialize)]
 pub struct ConfigProbeService {
     pub id: String,
+    pub interval: Option<u64>,
     pub node: Vec<ConfigProbeServiceNode>,
 }
 
@@ -58,6 +108,86 @@ pub struct ConfigProbeService {
 pub struct ConfigProbeServiceNode {
     pub id: String,
     pub mode: Mode,
+    pub interval: Option<u64>,
     pub replicas: Option<Vec<ReplicaURL>>,
-    pub scripts: Option<Vec<String>>,
+    pub scripts: Option<Vec<ScriptEntry>>,
+    pub script_timeout: Option<u64>,
+
+    #[serde(default)]
+    pub script_output: ScriptOutputMode,
+
+    #[serde(default)]
+    pub script_convention: ScriptConvention,
+
+    #[serde(default = "defaults::probe_service_node_script_unknown_status")]
+    pub script_unknown_status: Status,
+
+    pub http_method: Option<HTTPMethod>,
+    pub http_headers: Option<HashMap<String, String>>,
+    pub http_auth: Option<HTTPAuth>,
+    pub http_body: Option<String>,
+    pub http_status_healthy: Option<Vec<HTTPStatusRange>>,
+
+    #[serde(default = "defaults::probe_service_node_http_follow_redirects")]
+    pub http_follow_redirects: bool,
+
+    #[serde(default)]
+    #[serde(with = "serde_regex")]
+    pub http_body_healthy_match: Option<Regex>,
+
+    pub http_body_healthy_contains: Option<String>,
+    pub http_body_healthy_json: Option<HTTPBodyJSONMatch>,
+
+    #[serde(default = "defaults::probe_service_node_icmp_count")]
+    pub icmp_count: u16,
+
+    #[serde(default = "defaults::probe_service_node_icmp_interval_ms")]
+    pub icmp_interval_ms: u64,
+
+    pub icmp_timeout_ms: Option<u64>,
+
+    #[serde(default = "defaults::probe_service_node_icmp_loss_sick_percent")]
+    pub icmp_loss_sick_percent: u8,
+
+    #[serde(default = "defaults::probe_service_node_icmp_loss_dead_percent")]
+    pub icmp_loss_dead_percent: u8,
+
+    pub icmp_jitter_sick_ms: Option<u64>,
+    pub icmp_rtt_max_sick_ms: Option<u64>,
+
+    #[serde(default = "defaults::probe_service_node_tls_expiry_warn_days")]
+    pub tls_expiry_warn_days: u16,
+
+    pub tls_ca_file: Option<PathBuf>,
+    pub tls_cert_file: Option<PathBuf>,
+    pub tls_key_file: Option<PathBuf>,
+    pub tls_verify: Option<bool>,
+    pub tcp_payload: Option<String>,
+    pub tcp_banner_prefix: Option<String>,
+
+    #[serde(default)]
+    #[serde(with = "serde_regex")]
+    pub tcp_banner_match: Option<Regex>,
+
+    pub udp_payload: Option<String>,
+    pub udp_payload_hex: Option<UDPHexBytes>,
+    pub udp_response_prefix: Option<String>,
+    pub udp_response_prefix_hex: Option<UDPHexBytes>,
+
+    #[serde(default)]
+    pub udp_timeout_healthy: bool,
+
+    pub database_query: Option<String>,
+    pub database_query_expect: Option<String>,
+    pub websocket_message: Option<String>,
+
+    #[serde(default)]
+    #[serde(with = "serde_regex")]
+    pub websocket_response_match: Option<Regex>,
+}
+
+#[cfg(test)]
+pub fn test_node(mode: &str, extra: &str) -> ConfigProbeServiceNode {
+    toml::from_str(&format!("id = \"node\"\nmode = \"{}\"\n{}", mode, extra))
+        .expect("invalid test node")
 }
@@ -4,14 +4,38 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+use crate::probe::report::ReportTargetMode;
+use crate::probe::spool::SpoolReplay;
+use crate::probe::status::Status;
+
 pub fn server_log_level() -> String {
     "error".to_string()
 }
 
+pub fn report_target_mode() -> ReportTargetMode {
+    ReportTargetMode::Failover
+}
+
+pub fn report_spool_max_entries() -> usize {
+    10000
+}
+
+pub fn report_spool_max_age() -> u64 {
+    86400
+}
+
+pub fn report_spool_replay() -> SpoolReplay {
+    SpoolReplay::All
+}
+
 pub fn metrics_interval() -> u64 {
     120
 }
 
+pub fn metrics_max_concurrency() -> u16 {
+    4
+}
+
 pub fn metrics_poll_retry() -> u8 {
     2
 }
@@ -23,3 +47,39 @@ pub fn metrics_poll_delay_dead() -> u64 {
 pub fn metrics_poll_delay_sick() -> u64 {
     1
 }
+
+pub fn probe_tls_verify() -> bool {
+    true
+}
+
+pub fn probe_script_timeout() -> u64 {
+    60
+}
+
+pub fn probe_service_node_script_unknown_status() -> Status {
+    Status::Dead
+}
+
+pub fn probe_service_node_icmp_count() -> u16 {
+    1
+}
+
+pub fn probe_service_node_icmp_interval_ms() -> u64 {
+    200
+}
+
+pub fn probe_service_node_icmp_loss_sick_percent() -> u8 {
+    1
+}
+
+pub fn probe_service_node_icmp_loss_dead_percent() -> u8 {
+    100
+}
+
+pub fn probe_service_node_tls_expiry_warn_days() -> u16 {
+    14
+}
+
+pub fn probe_service_node_http_follow_redirects() -> bool {
+    true
+}
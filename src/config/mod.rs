@@ -6,6 +6,7 @@
 
 mod defaults;
 
+#[allow(clippy::module_inception)]
 pub mod config;
 pub mod logger;
 pub mod reader;
@@ -4,12 +4,18 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-use std::{collections::HashMap, env, fs};
+use std::{collections::HashMap, env, fs, path::Path};
 
 use envsubst::substitute;
 use toml;
 
 use super::config::*;
+use crate::probe::database::validate as database_validate;
+use crate::probe::icmp::validate as icmp_validate;
+use crate::probe::report::validate as report_validate;
+use crate::probe::script::resolve as script_resolve;
+use crate::probe::tls::{make_client_config as tls_make_client_config, TLSOptions};
+use crate::probe::udp::validate as udp_validate;
 use crate::APP_ARGS;
 
 pub struct ConfigReader;
@@ -29,6 +35,137 @@ impl ConfigReader {
         conf = substitute(&conf, &environment).expect("cannot substitute environment variables");
 
         // Parse configuration
-        toml::from_str(&conf).expect("syntax error in config file")
+        let mut config = toml::from_str(&conf).expect("syntax error in config file");
+
+        // Resolve and validate configuration (relative paths are resolved from the config file \
+        //   directory)
+        let directory = Path::new(&APP_ARGS.config)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+
+        Self::resolve(&mut config, directory);
+        Self::validate(&config);
+
+        config
+    }
+
+    fn resolve(config: &mut Config, directory: &Path) {
+        for service in config.probe.service.iter_mut() {
+            for node in service.node.iter_mut() {
+                // Resolve script files (so that a missing script fails upon startup)
+                if let Err(err) = script_resolve(node, directory) {
+                    panic!(
+                        "invalid script settings in config file for node: #{}:#{} ({})",
+                        service.id, node.id, err
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate(config: &Config) {
+        // Notice: fail hard upon invalid settings, as they would otherwise silently result in \
+        //   dead replicas being reported, which is hard to troubleshoot.
+        if let Err(err) = report_validate(&config.report) {
+            panic!("invalid report settings in config file ({})", err);
+        }
+
+        // Check intervals (a zero interval would make the scheduler spin)
+        if config.metrics.interval == 0 {
+            panic!("invalid metrics settings in config file (interval must be greater than zero)");
+        }
+
+        for service in &config.probe.service {
+            if service.interval == Some(0) {
+                panic!(
+                    "invalid interval settings in config file for service: #{} (interval must be \
+                        greater than zero)",
+                    service.id
+                );
+            }
+
+            for node in &service.node {
+                if node.interval == Some(0) {
+                    panic!(
+                        "invalid interval settings in config file for node: #{}:#{} (interval \
+                            must be greater than zero)",
+                        service.id, node.id
+                    );
+                }
+
+                // Check ICMP settings (eg. loss thresholds are valid percentages)
+                if let Err(err) = icmp_validate(node) {
+                    panic!(
+                        "invalid icmp settings in config file for node: #{}:#{} ({})",
+                        service.id, node.id, err
+                    );
+                }
+
+                // Check TLS settings (eg. certificate and key files can be read and parsed)
+                if let Err(err) =
+                    tls_make_client_config(&TLSOptions::from_config(&config.probe, node))
+                {
+                    panic!(
+                        "invalid tls settings in config file for node: #{}:#{} ({})",
+                        service.id, node.id, err
+                    );
+                }
+
+                // Check database settings (eg. PostgreSQL URLs do not require TLS)
+                if let Err(err) = database_validate(node) {
+                    panic!(
+                        "invalid database settings in config file for node: #{}:#{} ({})",
+                        service.id, node.id, err
+                    );
+                }
+
+                // Check UDP settings (eg. payload is not set both as text and hexadecimal)
+                if let Err(err) = udp_validate(node) {
+                    panic!(
+                        "invalid udp settings in config file for node: #{}:#{} ({})",
+                        service.id, node.id, err
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(metrics: &str, service: &str, node: &str) -> Config {
+        toml::from_str(&format!(
+            "[server]\n[report]\nendpoint = \"http://localhost\"\ntoken = \"x\"\n\
+                [metrics]\n{}\n[probe]\n[[probe.service]]\nid = \"s\"\n{}\n\
+                [[probe.service.node]]\nid = \"n\"\nmode = \"poll\"\n{}",
+            metrics, service, node
+        ))
+        .expect("invalid config")
+    }
+
+    #[test]
+    fn it_accepts_non_zero_intervals() {
+        ConfigReader::validate(&config("", "", ""));
+        ConfigReader::validate(&config("interval = 30", "interval = 10", "interval = 1"));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid metrics settings")]
+    fn it_rejects_zero_metrics_interval() {
+        ConfigReader::validate(&config("interval = 0", "", ""));
+    }
+
+    #[test]
+    #[should_panic(expected = "for service: #s (interval must be greater than zero)")]
+    fn it_rejects_zero_service_interval() {
+        ConfigReader::validate(&config("", "interval = 0", ""));
+    }
+
+    #[test]
+    #[should_panic(expected = "for node: #s:#n (interval must be greater than zero)")]
+    fn it_rejects_zero_node_interval() {
+        ConfigReader::validate(&config("", "", "interval = 0"));
     }
 }
@@ -13,6 +13,7 @@ extern crate serde_derive;
 
 mod config;
 mod probe;
+mod responder;
 
 use std::ops::Deref;
 use std::str::FromStr;
@@ -26,12 +27,15 @@ use config::config::Config;
 use config::logger::ConfigLogger;
 use config::reader::ConfigReader;
 use probe::manager::run as run_probe;
+use responder::manager::run as run_responder;
 
 struct AppArgs {
     config: String,
 }
 
-pub static THREAD_NAME_PROBE: &'static str = "vigil-local-probe";
+pub static THREAD_NAME_PROBE: &str = "vigil-local-probe";
+pub static THREAD_NAME_PROBE_WORKER: &str = "vigil-local-probe-worker";
+pub static THREAD_NAME_RESPONDER: &str = "vigil-local-responder";
 
 lazy_static! {
     static ref APP_ARGS: AppArgs = make_app_args();
@@ -66,6 +70,21 @@ fn ensure_states() {
     let (_, _) = (APP_ARGS.deref(), APP_CONF.deref());
 }
 
+fn spawn_responder() {
+    if let Some(inet) = APP_CONF.server.inet {
+        debug!("spawn managed thread: responder");
+
+        // Notice: the responder runs in the background, as the probe is the foreground thread.
+        let worker = thread::Builder::new()
+            .name(THREAD_NAME_RESPONDER.to_string())
+            .spawn(move || run_responder(inet));
+
+        if worker.is_err() {
+            error!("could not spawn managed thread (responder)");
+        }
+    }
+}
+
 fn spawn_probe() {
     debug!("spawn managed thread: probe");
 
@@ -81,7 +100,7 @@ fn spawn_probe() {
     };
 
     // Worker thread crashed?
-    if has_error == true {
+    if has_error {
         error!("managed thread crashed (probe), setting it up again");
 
         // Prevents thread start loop floods
@@ -103,6 +122,9 @@ fn main() {
     // Ensure all states are bound
     ensure_states();
 
+    // Spawn responder (background thread, if enabled)
+    spawn_responder();
+
     // Spawn probe (foreground thread)
     spawn_probe();
 
@@ -0,0 +1,183 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RecordType};
+use url::Url;
+
+use super::status::Status;
+
+pub const DNS_PORT_DEFAULT: u16 = 53;
+
+const DNS_RECORD_TYPE_DEFAULT: &str = "A";
+
+static DNS_QUERY_SEQUENCE: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DNSQuery {
+    pub name: String,
+    pub record_type: String,
+    pub expect: Vec<String>,
+}
+
+impl DNSQuery {
+    pub fn parse_from(url: &Url) -> Result<DNSQuery, ()> {
+        // Acquire queried name from path (eg. 'dns://resolver:53/example.com')
+        let name = url.path().trim_start_matches('/').to_owned();
+
+        if name.is_empty() || Name::from_ascii(&name).is_err() {
+            return Err(());
+        }
+
+        // Acquire record type and expected records from query (eg. '?type=A&expect=10.0.0.5')
+        let mut record_type = DNS_RECORD_TYPE_DEFAULT.to_string();
+        let mut expect = Vec::new();
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "type" => record_type = value.to_uppercase(),
+                "expect" => expect.push(value.into_owned()),
+                _ => return Err(()),
+            }
+        }
+
+        if RecordType::from_str(&record_type).is_err() {
+            return Err(());
+        }
+
+        Ok(DNSQuery {
+            name,
+            record_type,
+            expect,
+        })
+    }
+
+    pub fn encode(&self) -> Result<(u16, Vec<u8>), ()> {
+        let name = Name::from_ascii(&self.name).map_err(|_| ())?;
+        let record_type = RecordType::from_str(&self.record_type).map_err(|_| ())?;
+
+        // Notice: query identifiers do not need to be secure here, as responses are only read \
+        //   from the configured resolver, they only need to vary across queries.
+        let id = acquire_id();
+
+        let mut message = Message::new();
+
+        message
+            .set_id(id)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(name, record_type));
+
+        message
+            .to_vec()
+            .map(|query| (id, query))
+            .map_err(|err| error!("prober poll could not encode dns query: {}", err))
+    }
+}
+
+pub fn decode(id: u16, response: &[u8]) -> Result<Message, ()> {
+    match Message::from_vec(response) {
+        Ok(message) => {
+            if message.id() == id && message.message_type() == MessageType::Response {
+                Ok(message)
+            } else {
+                debug!(
+                    "prober poll got unrelated dns response (id: {})",
+                    message.id()
+                );
+
+                Err(())
+            }
+        }
+        Err(err) => {
+            debug!("prober poll got invalid dns response (error: {})", err);
+
+            Err(())
+        }
+    }
+}
+
+pub fn exchange<S: Read + Write>(stream: &mut S, query: &[u8]) -> Result<Vec<u8>, ()> {
+    // Notice: DNS messages over TCP are prefixed with their size, as a 2-byte integer.
+    let query_size = (query.len() as u16).to_be_bytes();
+
+    stream
+        .write_all(&query_size)
+        .and_then(|_| stream.write_all(query))
+        .and_then(|_| stream.flush())
+        .map_err(|err| debug!("prober poll could not send dns query over tcp: {}", err))?;
+
+    let mut response_size = [0; 2];
+
+    stream
+        .read_exact(&mut response_size)
+        .map_err(|err| debug!("prober poll could not read dns response over tcp: {}", err))?;
+
+    let mut response = vec![0; u16::from_be_bytes(response_size) as usize];
+
+    stream
+        .read_exact(&mut response)
+        .map_err(|err| debug!("prober poll could not read dns response over tcp: {}", err))?;
+
+    Ok(response)
+}
+
+pub fn evaluate(query: &DNSQuery, response: &Message) -> Status {
+    // Check response code (the resolver failed to answer, or the name does not exist)
+    let response_code = response.response_code();
+
+    if response_code != ResponseCode::NoError {
+        debug!(
+            "prober poll got dns response code: {} for name: {}",
+            response_code, query.name
+        );
+
+        return Status::Dead;
+    }
+
+    // Check expected records (all expected values must be found in answered records)
+    if !query.expect.is_empty() {
+        let answers: Vec<String> = response
+            .answers()
+            .iter()
+            .filter(|record| record.record_type().to_string() == query.record_type)
+            .map(|record| normalize(&record.data().to_string()))
+            .collect();
+
+        for expect in &query.expect {
+            if !answers.contains(&normalize(expect)) {
+                debug!(
+                    "prober poll did not get expected dns record: {} for name: {} (got: {:?})",
+                    expect, query.name, answers
+                );
+
+                return Status::Sick;
+            }
+        }
+    }
+
+    Status::Healthy
+}
+
+fn normalize(value: &str) -> String {
+    // Notice: names are compared case-insensitively, and regardless of the trailing root dot.
+    value.trim().trim_end_matches('.').to_lowercase()
+}
+
+fn acquire_id() -> u16 {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos() as u16)
+        .unwrap_or(0);
+
+    seed ^ DNS_QUERY_SEQUENCE.fetch_add(1, Ordering::Relaxed)
+}
@@ -0,0 +1,658 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::io::{Read, Write};
+
+use super::hpack::{self, Decoder};
+use super::http::POLL_HTTP_HEADER_USERAGENT;
+use super::status::Status;
+
+pub const GRPC_ALPN_PROTOCOL: &str = "h2";
+
+const GRPC_HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+const GRPC_STATUS_OK: &str = "0";
+const GRPC_STATUS_NOT_FOUND: &str = "5";
+
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const HTTP2_STREAM_ID: u32 = 1;
+const HTTP2_FRAME_MAXIMUM_SIZE: usize = 1048576;
+
+const HTTP2_FRAME_DATA: u8 = 0x0;
+const HTTP2_FRAME_HEADERS: u8 = 0x1;
+const HTTP2_FRAME_RST_STREAM: u8 = 0x3;
+const HTTP2_FRAME_SETTINGS: u8 = 0x4;
+const HTTP2_FRAME_PING: u8 = 0x6;
+const HTTP2_FRAME_GOAWAY: u8 = 0x7;
+const HTTP2_FRAME_CONTINUATION: u8 = 0x9;
+
+const HTTP2_FLAG_END_STREAM: u8 = 0x1;
+const HTTP2_FLAG_ACK: u8 = 0x1;
+const HTTP2_FLAG_END_HEADERS: u8 = 0x4;
+const HTTP2_FLAG_PADDED: u8 = 0x8;
+const HTTP2_FLAG_PRIORITY: u8 = 0x20;
+
+struct HTTP2Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct GRPCResponse {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+pub fn request<S: Read + Write>(
+    authority: &str,
+    service: &str,
+    is_secure: bool,
+    stream: &mut S,
+) -> Result<GRPCResponse, ()> {
+    debug!(
+        "prober poll will check grpc health for service: '{}' on: {}",
+        service, authority
+    );
+
+    // Encode request headers
+    let header_block = hpack::encode(&[
+        (":method", "POST"),
+        (":scheme", if is_secure { "https" } else { "http" }),
+        (":path", GRPC_HEALTH_CHECK_PATH),
+        (":authority", authority),
+        ("content-type", "application/grpc"),
+        ("te", "trailers"),
+        ("user-agent", &POLL_HTTP_HEADER_USERAGENT),
+    ]);
+
+    // Send connection preface, then request headers and request message
+    let result = stream
+        .write_all(HTTP2_PREFACE)
+        .and_then(|_| write_frame(stream, HTTP2_FRAME_SETTINGS, 0, 0, &[]))
+        .and_then(|_| {
+            write_frame(
+                stream,
+                HTTP2_FRAME_HEADERS,
+                HTTP2_FLAG_END_HEADERS,
+                HTTP2_STREAM_ID,
+                &header_block,
+            )
+        })
+        .and_then(|_| {
+            write_frame(
+                stream,
+                HTTP2_FRAME_DATA,
+                HTTP2_FLAG_END_STREAM,
+                HTTP2_STREAM_ID,
+                &encode_message(service),
+            )
+        })
+        .and_then(|_| stream.flush());
+
+    if let Err(err) = result {
+        debug!("prober poll could not send grpc request: {}", err);
+
+        return Err(());
+    }
+
+    // Read frames until the response stream ends
+    let mut decoder = Decoder::default();
+    let mut response = GRPCResponse::default();
+    let mut header_block = Vec::new();
+    let mut has_ended = false;
+
+    loop {
+        let frame = read_frame(stream)?;
+
+        match frame.kind {
+            HTTP2_FRAME_SETTINGS if frame.flags & HTTP2_FLAG_ACK == 0 => {
+                write_frame(stream, HTTP2_FRAME_SETTINGS, HTTP2_FLAG_ACK, 0, &[])
+                    .map_err(|err| debug!("prober poll could not ack http2 settings: {}", err))?;
+            }
+            HTTP2_FRAME_PING if frame.flags & HTTP2_FLAG_ACK == 0 => {
+                write_frame(stream, HTTP2_FRAME_PING, HTTP2_FLAG_ACK, 0, &frame.payload)
+                    .map_err(|err| debug!("prober poll could not ack http2 ping: {}", err))?;
+            }
+            HTTP2_FRAME_GOAWAY => {
+                debug!("prober poll got http2 goaway from grpc target");
+
+                return Err(());
+            }
+            HTTP2_FRAME_RST_STREAM if frame.stream_id == HTTP2_STREAM_ID => {
+                debug!("prober poll got http2 stream reset from grpc target");
+
+                return Err(());
+            }
+            HTTP2_FRAME_HEADERS | HTTP2_FRAME_CONTINUATION
+                if frame.stream_id == HTTP2_STREAM_ID =>
+            {
+                header_block.extend_from_slice(strip_padding(&frame)?);
+
+                // Decode header block once complete (it may span over continuation frames)
+                if frame.flags & HTTP2_FLAG_END_HEADERS != 0 {
+                    let headers = decoder
+                        .decode(&header_block)
+                        .map_err(|err| debug!("prober poll got invalid grpc headers: {:?}", err))?;
+
+                    for (name, value) in headers {
+                        response.headers.push((
+                            String::from_utf8_lossy(&name).into_owned(),
+                            String::from_utf8_lossy(&value).into_owned(),
+                        ));
+                    }
+
+                    header_block.clear();
+                }
+            }
+            HTTP2_FRAME_DATA if frame.stream_id == HTTP2_STREAM_ID => {
+                response.body.extend_from_slice(strip_padding(&frame)?);
+            }
+            _ => {}
+        }
+
+        if frame.stream_id == HTTP2_STREAM_ID
+            && frame.flags & HTTP2_FLAG_END_STREAM != 0
+            && (frame.kind == HTTP2_FRAME_DATA || frame.kind == HTTP2_FRAME_HEADERS)
+        {
+            has_ended = true;
+        }
+
+        // Notice: a trailers frame ending the stream may be followed by continuation frames, \
+        //   thus the response is complete only once its header block is complete.
+        if has_ended && header_block.is_empty() {
+            return Ok(response);
+        }
+    }
+}
+
+pub fn evaluate(service: &str, response: &GRPCResponse) -> Status {
+    // Check HTTP status (the target is not a gRPC server)
+    if response.header(":status") != Some("200") {
+        debug!(
+            "prober poll got grpc http status: {:?} for service: '{}'",
+            response.header(":status"),
+            service
+        );
+
+        return Status::Dead;
+    }
+
+    // Check gRPC status (an unknown service is considered as sick)
+    match response.header("grpc-status") {
+        Some(GRPC_STATUS_OK) => {}
+        Some(grpc_status) => {
+            debug!(
+                "prober poll got grpc status: {} for service: '{}' (message: {:?})",
+                grpc_status,
+                service,
+                response.header("grpc-message")
+            );
+
+            return if grpc_status == GRPC_STATUS_NOT_FOUND {
+                Status::Sick
+            } else {
+                Status::Dead
+            };
+        }
+        None => {
+            debug!("prober poll got no grpc status for service: '{}'", service);
+
+            return Status::Dead;
+        }
+    }
+
+    // Check serving status (0 is 'UNKNOWN', 1 is 'SERVING', 2 is 'NOT_SERVING' and 3 is \
+    //   'SERVICE_UNKNOWN')
+    let serving_status = match decode_message(&response.body) {
+        Some(serving_status) => serving_status,
+        None => {
+            debug!("prober poll got invalid grpc health response");
+
+            return Status::Dead;
+        }
+    };
+
+    debug!(
+        "prober poll got grpc serving status: {} for service: '{}'",
+        serving_status, service
+    );
+
+    match serving_status {
+        1 => Status::Healthy,
+        2 => Status::Dead,
+        _ => Status::Sick,
+    }
+}
+
+impl GRPCResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        // Notice: gRPC status headers are sent in trailers, thus the last value is preferred.
+        self.headers
+            .iter()
+            .rev()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, header_value)| header_value.as_str())
+    }
+}
+
+fn encode_message(service: &str) -> Vec<u8> {
+    // Encode 'HealthCheckRequest' (field #1 is the service name, omitted if empty)
+    let mut message = Vec::new();
+
+    if !service.is_empty() {
+        message.push(0x0a);
+        encode_varint(&mut message, service.len() as u64);
+        message.extend_from_slice(service.as_bytes());
+    }
+
+    // Prefix with gRPC message header (uncompressed flag, then message length)
+    let mut frame = vec![0];
+
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+
+    frame
+}
+
+fn decode_message(body: &[u8]) -> Option<u64> {
+    // Skip gRPC message header (compressed messages are not supported)
+    if body.len() < 5 || body[0] != 0 {
+        return None;
+    }
+
+    let message_size = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let mut message = body.get(5..(5 + message_size))?;
+
+    // Decode 'HealthCheckResponse' (field #1 is the serving status, defaults to 'UNKNOWN')
+    let mut serving_status = 0;
+
+    while !message.is_empty() {
+        let tag = decode_varint(&mut message)?;
+
+        match tag & 0x7 {
+            0 => {
+                let value = decode_varint(&mut message)?;
+
+                if tag >> 3 == 1 {
+                    serving_status = value;
+                }
+            }
+            2 => {
+                let size = decode_varint(&mut message)? as usize;
+
+                message = message.get(size..)?;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(serving_status)
+}
+
+fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn decode_varint(buffer: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = buffer.split_first()?;
+
+        *buffer = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn write_frame<S: Write>(
+    stream: &mut S,
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: &[u8],
+) -> Result<(), std::io::Error> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    stream.write_all(&frame)
+}
+
+fn read_frame<S: Read>(stream: &mut S) -> Result<HTTP2Frame, ()> {
+    let mut header = [0; 9];
+
+    stream
+        .read_exact(&mut header)
+        .map_err(|err| debug!("prober poll could not read http2 frame: {}", err))?;
+
+    let size = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+
+    if size > HTTP2_FRAME_MAXIMUM_SIZE {
+        debug!("prober poll got too large http2 frame: {} bytes", size);
+
+        return Err(());
+    }
+
+    let mut payload = vec![0; size];
+
+    stream
+        .read_exact(&mut payload)
+        .map_err(|err| debug!("prober poll could not read http2 frame: {}", err))?;
+
+    Ok(HTTP2Frame {
+        kind: header[3],
+        flags: header[4],
+        stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fffffff,
+        payload,
+    })
+}
+
+fn strip_padding(frame: &HTTP2Frame) -> Result<&[u8], ()> {
+    let mut payload = &frame.payload[..];
+
+    // Strip padding length and padding (if padded)
+    if frame.flags & HTTP2_FLAG_PADDED != 0 {
+        let (padding_size, rest) = payload.split_first().ok_or(())?;
+
+        payload = rest
+            .get(..(rest.len().checked_sub(*padding_size as usize).ok_or(())?))
+            .ok_or(())?;
+    }
+
+    // Strip stream dependency and weight (if headers frame with priority)
+    if frame.kind == HTTP2_FRAME_HEADERS && frame.flags & HTTP2_FLAG_PRIORITY != 0 {
+        payload = payload.get(5..).ok_or(())?;
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{self, Cursor};
+
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn health_body(serving_status: u64) -> Vec<u8> {
+        let mut message = vec![0x08];
+
+        encode_varint(&mut message, serving_status);
+
+        let mut body = vec![0];
+
+        body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        body.extend_from_slice(&message);
+
+        body
+    }
+
+    fn response(headers: &[(&str, &str)], body: Vec<u8>) -> GRPCResponse {
+        GRPCResponse {
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body,
+        }
+    }
+
+    #[test]
+    fn it_encodes_and_decodes_frames() {
+        let mut buffer = Vec::new();
+
+        write_frame(&mut buffer, HTTP2_FRAME_DATA, 0x5, 3, b"hello").unwrap();
+
+        assert_eq!(&buffer[..9], &[0, 0, 5, 0x0, 0x5, 0, 0, 0, 3]);
+
+        let frame = read_frame(&mut Cursor::new(buffer)).unwrap();
+
+        assert_eq!(frame.kind, HTTP2_FRAME_DATA);
+        assert_eq!(frame.flags, 0x5);
+        assert_eq!(frame.stream_id, 3);
+        assert_eq!(frame.payload, b"hello");
+    }
+
+    #[test]
+    fn it_masks_reserved_stream_bit() {
+        let frame = read_frame(&mut Cursor::new(vec![0, 0, 0, 0x4, 0, 0x80, 0, 0, 1])).unwrap();
+
+        assert_eq!(frame.stream_id, 1);
+    }
+
+    #[test]
+    fn it_rejects_invalid_frames() {
+        // Truncated payload
+        assert!(read_frame(&mut Cursor::new(vec![0, 0, 5, 0, 0, 0, 0, 0, 1, b'a'])).is_err());
+
+        // Frame larger than the maximum size
+        assert!(read_frame(&mut Cursor::new(vec![0x10, 0, 1, 0, 0, 0, 0, 0, 1])).is_err());
+    }
+
+    #[test]
+    fn it_strips_padding_and_priority() {
+        let frame = HTTP2Frame {
+            kind: HTTP2_FRAME_HEADERS,
+            flags: HTTP2_FLAG_PADDED | HTTP2_FLAG_PRIORITY,
+            stream_id: HTTP2_STREAM_ID,
+            payload: vec![2, 0, 0, 0, 0, 16, b'h', b'i', 0, 0],
+        };
+
+        assert_eq!(strip_padding(&frame).unwrap(), b"hi");
+
+        let frame = HTTP2Frame {
+            kind: HTTP2_FRAME_DATA,
+            flags: HTTP2_FLAG_PADDED,
+            stream_id: HTTP2_STREAM_ID,
+            payload: vec![4, b'h', b'i'],
+        };
+
+        assert!(strip_padding(&frame).is_err());
+    }
+
+    #[test]
+    fn it_encodes_and_decodes_varints() {
+        for value in [0, 1, 127, 128, 300, 16384, u32::MAX as u64, u64::MAX] {
+            let mut buffer = Vec::new();
+
+            encode_varint(&mut buffer, value);
+
+            let mut slice = &buffer[..];
+
+            assert_eq!(decode_varint(&mut slice), Some(value));
+            assert!(slice.is_empty());
+        }
+
+        let mut buffer = Vec::new();
+
+        encode_varint(&mut buffer, 300);
+
+        assert_eq!(buffer, [0xac, 0x02]);
+
+        // Truncated varint
+        assert_eq!(decode_varint(&mut &[0x80, 0x80][..]), None);
+    }
+
+    #[test]
+    fn it_encodes_request_message() {
+        assert_eq!(encode_message(""), [0, 0, 0, 0, 0]);
+        assert_eq!(
+            encode_message("app"),
+            [0, 0, 0, 0, 5, 0x0a, 3, b'a', b'p', b'p']
+        );
+    }
+
+    #[test]
+    fn it_decodes_response_message() {
+        assert_eq!(decode_message(&health_body(1)), Some(1));
+
+        // Empty message (serving status defaults to 'UNKNOWN')
+        assert_eq!(decode_message(&[0, 0, 0, 0, 0]), Some(0));
+
+        // Unknown length-delimited field is skipped
+        assert_eq!(
+            decode_message(&[0, 0, 0, 0, 5, 0x12, 1, b'x', 0x08, 2]),
+            Some(2)
+        );
+
+        // Compressed or truncated messages
+        assert_eq!(decode_message(&[1, 0, 0, 0, 2, 0x08, 1]), None);
+        assert_eq!(decode_message(&[0, 0, 0, 0, 2, 0x08]), None);
+    }
+
+    #[test]
+    fn it_extracts_status_from_trailers() {
+        let mut input = Vec::new();
+
+        write_frame(&mut input, HTTP2_FRAME_SETTINGS, 0, 0, &[]).unwrap();
+        write_frame(
+            &mut input,
+            HTTP2_FRAME_HEADERS,
+            HTTP2_FLAG_END_HEADERS,
+            HTTP2_STREAM_ID,
+            &hpack::encode(&[(":status", "200"), ("content-type", "application/grpc")]),
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            HTTP2_FRAME_DATA,
+            0,
+            HTTP2_STREAM_ID,
+            &health_body(1),
+        )
+        .unwrap();
+
+        // Trailers span over a continuation frame
+        let trailers = hpack::encode(&[("grpc-status", "0"), ("grpc-message", "")]);
+
+        write_frame(
+            &mut input,
+            HTTP2_FRAME_HEADERS,
+            HTTP2_FLAG_END_STREAM,
+            HTTP2_STREAM_ID,
+            &trailers[..1],
+        )
+        .unwrap();
+        write_frame(
+            &mut input,
+            HTTP2_FRAME_CONTINUATION,
+            HTTP2_FLAG_END_HEADERS,
+            HTTP2_STREAM_ID,
+            &trailers[1..],
+        )
+        .unwrap();
+
+        let mut stream = MockStream {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+
+        let response = request("localhost:50051", "app", false, &mut stream).unwrap();
+
+        assert!(stream.output.starts_with(HTTP2_PREFACE));
+        assert_eq!(response.header(":status"), Some("200"));
+        assert_eq!(response.header("grpc-status"), Some("0"));
+        assert_eq!(evaluate("app", &response), Status::Healthy);
+    }
+
+    #[test]
+    fn it_prefers_last_status_header() {
+        let response = response(&[("grpc-status", "0"), ("grpc-status", "14")], Vec::new());
+
+        assert_eq!(response.header("grpc-status"), Some("14"));
+    }
+
+    #[test]
+    fn it_maps_serving_status() {
+        let ok = [(":status", "200"), ("grpc-status", "0")];
+
+        assert_eq!(
+            evaluate("app", &response(&ok, health_body(1))),
+            Status::Healthy
+        );
+        assert_eq!(
+            evaluate("app", &response(&ok, health_body(2))),
+            Status::Dead
+        );
+        assert_eq!(
+            evaluate("app", &response(&ok, health_body(0))),
+            Status::Sick
+        );
+        assert_eq!(
+            evaluate("app", &response(&ok, health_body(3))),
+            Status::Sick
+        );
+        assert_eq!(evaluate("app", &response(&ok, Vec::new())), Status::Dead);
+    }
+
+    #[test]
+    fn it_maps_grpc_and_http_status() {
+        let body = health_body(1);
+
+        assert_eq!(
+            evaluate(
+                "app",
+                &response(&[(":status", "200"), ("grpc-status", "5")], body.clone())
+            ),
+            Status::Sick
+        );
+        assert_eq!(
+            evaluate(
+                "app",
+                &response(&[(":status", "200"), ("grpc-status", "14")], body.clone())
+            ),
+            Status::Dead
+        );
+        assert_eq!(
+            evaluate("app", &response(&[(":status", "200")], body.clone())),
+            Status::Dead
+        );
+        assert_eq!(
+            evaluate(
+                "app",
+                &response(&[(":status", "404"), ("grpc-status", "0")], body)
+            ),
+            Status::Dead
+        );
+    }
+}
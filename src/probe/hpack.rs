@@ -0,0 +1,697 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::collections::{HashMap, VecDeque};
+
+// Notice: this is a minimal HPACK (RFC 7541) implementation, as needed by the gRPC prober. \
+//   The encoder never indexes headers nor uses Huffman coding, while the decoder supports \
+//   every representation, as those are picked by the peer.
+
+const HPACK_TABLE_SIZE_MAXIMUM: usize = 4096;
+const HPACK_TABLE_ENTRY_OVERHEAD: usize = 32;
+const HPACK_INTEGER_SHIFT_MAXIMUM: u32 = 21;
+const HPACK_HUFFMAN_CODE_SIZE_MAXIMUM: u8 = 30;
+
+static HPACK_STATIC_TABLE: &[(&str, &str)] = &[
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Notice: codes are indexed by symbol, the last one being the end-of-string code (which is \
+//   only valid as padding).
+static HPACK_HUFFMAN_CODES: &[(u32, u8)] = &[
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+lazy_static! {
+    static ref HPACK_HUFFMAN_SYMBOLS: HashMap<(u32, u8), u8> = HPACK_HUFFMAN_CODES
+        .iter()
+        .take(256)
+        .enumerate()
+        .map(|(symbol, code)| (*code, symbol as u8))
+        .collect();
+}
+
+pub type Header = (Vec<u8>, Vec<u8>);
+
+pub struct Decoder {
+    table: VecDeque<Header>,
+    table_size: usize,
+    table_size_maximum: usize,
+}
+
+pub fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+
+    for (name, value) in headers {
+        // Literal header field without indexing, with a literal name
+        block.push(0x00);
+
+        encode_string(&mut block, name.as_bytes());
+        encode_string(&mut block, value.as_bytes());
+    }
+
+    block
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder {
+            table: VecDeque::new(),
+            table_size: 0,
+            table_size_maximum: HPACK_TABLE_SIZE_MAXIMUM,
+        }
+    }
+}
+
+impl Decoder {
+    pub fn decode(&mut self, mut block: &[u8]) -> Result<Vec<Header>, ()> {
+        let mut headers = Vec::new();
+
+        while let Some(first) = block.first() {
+            if first & 0x80 != 0 {
+                // Indexed header field
+                let index = decode_integer(&mut block, 7)?;
+
+                headers.push(self.entry(index)?);
+            } else if first & 0x40 != 0 {
+                // Literal header field with incremental indexing
+                let header = self.decode_literal(&mut block, 6)?;
+
+                self.insert(header.clone());
+
+                headers.push(header);
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update (cannot exceed the default maximum, as no other \
+                //   maximum is advertised)
+                let size = decode_integer(&mut block, 5)?;
+
+                if size > HPACK_TABLE_SIZE_MAXIMUM {
+                    return Err(());
+                }
+
+                self.table_size_maximum = size;
+
+                self.evict(0);
+            } else {
+                // Literal header field without indexing (or never indexed)
+                headers.push(self.decode_literal(&mut block, 4)?);
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn decode_literal(&self, block: &mut &[u8], prefix: u8) -> Result<Header, ()> {
+        let name = match decode_integer(block, prefix)? {
+            0 => decode_string(block)?,
+            index => self.entry(index)?.0,
+        };
+
+        Ok((name, decode_string(block)?))
+    }
+
+    fn entry(&self, index: usize) -> Result<Header, ()> {
+        // Notice: indexes start at 1, the dynamic table following the static table, with its \
+        //   newest entry first.
+        match index.checked_sub(1) {
+            Some(index) if index < HPACK_STATIC_TABLE.len() => {
+                let (name, value) = HPACK_STATIC_TABLE[index];
+
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            Some(index) => self
+                .table
+                .get(index - HPACK_STATIC_TABLE.len())
+                .cloned()
+                .ok_or(()),
+            None => Err(()),
+        }
+    }
+
+    fn insert(&mut self, header: Header) {
+        let size = entry_size(&header);
+
+        self.evict(size);
+
+        // Notice: an entry larger than the table empties it, without being inserted.
+        if size <= self.table_size_maximum {
+            self.table_size += size;
+
+            self.table.push_front(header);
+        }
+    }
+
+    fn evict(&mut self, reserved_size: usize) {
+        while self.table_size + reserved_size > self.table_size_maximum {
+            match self.table.pop_back() {
+                Some(header) => self.table_size -= entry_size(&header),
+                None => break,
+            }
+        }
+    }
+}
+
+fn entry_size(header: &Header) -> usize {
+    header.0.len() + header.1.len() + HPACK_TABLE_ENTRY_OVERHEAD
+}
+
+fn encode_integer(buffer: &mut Vec<u8>, prefix: u8, mut value: usize) {
+    let mask = 0xff >> (8 - prefix);
+
+    if value < mask as usize {
+        buffer.push(value as u8);
+
+        return;
+    }
+
+    buffer.push(mask);
+
+    value -= mask as usize;
+
+    while value >= 0x80 {
+        buffer.push((value & 0x7f) as u8 | 0x80);
+
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn decode_integer(buffer: &mut &[u8], prefix: u8) -> Result<usize, ()> {
+    let mask = 0xff >> (8 - prefix);
+    let (first, rest) = buffer.split_first().ok_or(())?;
+
+    *buffer = rest;
+
+    let mut value = (first & mask) as usize;
+
+    if value < mask as usize {
+        return Ok(value);
+    }
+
+    // Notice: the integer size is bounded, as it would otherwise never end on invalid input.
+    let mut shift = 0;
+
+    while shift <= HPACK_INTEGER_SHIFT_MAXIMUM {
+        let (byte, rest) = buffer.split_first().ok_or(())?;
+
+        *buffer = rest;
+
+        value += ((byte & 0x7f) as usize) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+
+        shift += 7;
+    }
+
+    Err(())
+}
+
+fn encode_string(buffer: &mut Vec<u8>, value: &[u8]) {
+    encode_integer(buffer, 7, value.len());
+
+    buffer.extend_from_slice(value);
+}
+
+fn decode_string(buffer: &mut &[u8]) -> Result<Vec<u8>, ()> {
+    let is_huffman = buffer.first().ok_or(())? & 0x80 != 0;
+    let size = decode_integer(buffer, 7)?;
+
+    let value = buffer.get(..size).ok_or(())?;
+
+    *buffer = &buffer[size..];
+
+    if is_huffman {
+        decode_huffman(value)
+    } else {
+        Ok(value.to_vec())
+    }
+}
+
+fn decode_huffman(value: &[u8]) -> Result<Vec<u8>, ()> {
+    let (mut decoded, mut code, mut code_size) = (Vec::new(), 0_u32, 0_u8);
+
+    for byte in value {
+        for shift in (0..8).rev() {
+            code = (code << 1) | ((byte >> shift) & 0x1) as u32;
+            code_size += 1;
+
+            if let Some(symbol) = HPACK_HUFFMAN_SYMBOLS.get(&(code, code_size)) {
+                decoded.push(*symbol);
+
+                (code, code_size) = (0, 0);
+            } else if code_size >= HPACK_HUFFMAN_CODE_SIZE_MAXIMUM {
+                // Invalid code, or end-of-string code found in string
+                return Err(());
+            }
+        }
+    }
+
+    // Check padding (it must be shorter than 8 bits, and be a prefix of the end-of-string \
+    //   code, which is made of ones only)
+    if code_size >= 8 || code != (1 << code_size) - 1 {
+        return Err(());
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(value: &str) -> Vec<u8> {
+        (0..value.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&value[index..(index + 2)], 16).unwrap())
+            .collect()
+    }
+
+    fn headers(headers: &[(&str, &str)]) -> Vec<Header> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn it_encodes_and_decodes_integers() {
+        for (prefix, value, encoded) in [(5, 10, vec![0x0a]), (5, 1337, vec![0x1f, 0x9a, 0x0a])] {
+            let mut buffer = Vec::new();
+
+            encode_integer(&mut buffer, prefix, value);
+
+            assert_eq!(buffer, encoded);
+            assert_eq!(decode_integer(&mut &encoded[..], prefix), Ok(value));
+        }
+
+        // Truncated and endless integers
+        assert!(decode_integer(&mut &[0x1f, 0x9a][..], 5).is_err());
+        assert!(decode_integer(&mut &[0x1f, 0xff, 0xff, 0xff, 0xff, 0x01][..], 5).is_err());
+    }
+
+    #[test]
+    fn it_decodes_encoded_headers() {
+        let block = encode(&[(":status", "200"), ("grpc-message", "")]);
+
+        assert_eq!(
+            Decoder::default().decode(&block),
+            Ok(headers(&[(":status", "200"), ("grpc-message", "")]))
+        );
+    }
+
+    #[test]
+    fn it_decodes_huffman_requests() {
+        // Requests from RFC 7541 (appendix C.4), sharing a dynamic table
+        let mut decoder = Decoder::default();
+
+        assert_eq!(
+            decoder.decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff")),
+            Ok(headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ]))
+        );
+        assert_eq!(
+            decoder.decode(&hex("828684be5886a8eb10649cbf")),
+            Ok(headers(&[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ]))
+        );
+        assert_eq!(
+            decoder.decode(&hex("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf")),
+            Ok(headers(&[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn it_evicts_oldest_entries() {
+        let mut decoder = Decoder::default();
+
+        // Shrink table to 64 bytes (fitting a single entry), then insert two entries
+        let mut block = vec![0x3f, 0x21];
+
+        for (name, value) in [("a", "1"), ("b", "2")] {
+            block.push(0x40);
+
+            encode_string(&mut block, name.as_bytes());
+            encode_string(&mut block, value.as_bytes());
+        }
+
+        decoder.decode(&block).unwrap();
+
+        assert_eq!(decoder.table_size, 34);
+        assert_eq!(decoder.entry(62), Ok(headers(&[("b", "2")])[0].clone()));
+        assert!(decoder.entry(63).is_err());
+    }
+
+    #[test]
+    fn it_rejects_invalid_blocks() {
+        // Index zero, and index out of the tables
+        assert!(Decoder::default().decode(&[0x80]).is_err());
+        assert!(Decoder::default().decode(&[0xbe]).is_err());
+
+        // Truncated string
+        assert!(Decoder::default().decode(&[0x00, 0x05, b'a']).is_err());
+
+        // Table size update larger than the maximum
+        assert!(Decoder::default().decode(&[0x3f, 0xe2, 0x1f]).is_err());
+
+        // Huffman padding longer than 7 bits, or not made of ones
+        assert!(decode_huffman(&[0xff, 0xff]).is_err());
+        assert!(decode_huffman(&[0x00]).is_err());
+    }
+}
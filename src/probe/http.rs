@@ -0,0 +1,698 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+
+use http_req::{
+    chunked::ChunkReader,
+    request::{Authentication, Method, RequestMessage},
+    response::Response,
+    uri::Uri,
+};
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value as JSONValue;
+use url::Url;
+
+use super::replica::ReplicaURL;
+use super::status::Status;
+use crate::config::config::ConfigProbeServiceNode;
+
+pub const HTTP_REDIRECT_MAXIMUM: usize = 5;
+
+const HTTP_STATUS_HEALTHY_ABOVE: u16 = 200;
+const HTTP_STATUS_HEALTHY_BELOW: u16 = 400;
+
+const HTTP_RESPONSE_HEAD_MAXIMUM_SIZE: u64 = 65536;
+const HTTP_RESPONSE_BODY_MAXIMUM_SIZE: u64 = 1048576;
+
+lazy_static! {
+    pub static ref POLL_HTTP_HEADER_USERAGENT: String =
+        format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub enum HTTPMethod {
+    #[serde(rename = "HEAD")]
+    Head,
+
+    #[serde(rename = "GET")]
+    Get,
+
+    #[serde(rename = "POST")]
+    Post,
+
+    #[serde(rename = "PUT")]
+    Put,
+
+    #[serde(rename = "PATCH")]
+    Patch,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HTTPStatusRange(u16, u16);
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum HTTPAuth {
+    #[serde(rename = "basic")]
+    Basic { username: String, password: String },
+
+    #[serde(rename = "bearer")]
+    Bearer { token: String },
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HTTPBodyJSONMatch {
+    pub pointer: String,
+    pub equals: JSONValue,
+}
+
+pub struct HTTPRequest<'a> {
+    pub url: String,
+    method: HTTPMethod,
+    headers: Vec<(String, String)>,
+    body: Option<&'a str>,
+}
+
+pub struct HTTPResponse {
+    pub status_code: u16,
+    pub body: Vec<u8>,
+    pub location: Option<String>,
+}
+
+impl HTTPMethod {
+    pub fn as_method(&self) -> Method {
+        match self {
+            Self::Head => Method::HEAD,
+            Self::Get => Method::GET,
+            Self::Post => Method::POST,
+            Self::Put => Method::PUT,
+            Self::Patch => Method::PATCH,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Head => "HEAD",
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Patch => "PATCH",
+        }
+    }
+}
+
+impl HTTPAuth {
+    pub fn as_header(&self) -> (String, String) {
+        match self {
+            Self::Basic { username, password } => {
+                Authentication::basic(username, password).header()
+            }
+            Self::Bearer { token } => Authentication::bearer(token).header(),
+        }
+    }
+}
+
+impl HTTPStatusRange {
+    pub fn parse_from(raw_range: &str) -> Result<HTTPStatusRange, ()> {
+        // Either a single status code (eg. '200') or an inclusive range (eg. '200-299')
+        let mut bounds = raw_range.trim().splitn(2, '-');
+
+        let start = bounds.next().and_then(|bound| bound.trim().parse().ok());
+        let end = match bounds.next() {
+            Some(bound) => bound.trim().parse().ok(),
+            None => start,
+        };
+
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => Ok(HTTPStatusRange(start, end)),
+            _ => Err(()),
+        }
+    }
+
+    pub fn contains(&self, status_code: u16) -> bool {
+        status_code >= self.0 && status_code <= self.1
+    }
+}
+
+impl<'a> HTTPRequest<'a> {
+    pub fn new(node: &'a ConfigProbeServiceNode, url: &str) -> HTTPRequest<'a> {
+        HTTPRequest {
+            url: url.to_owned(),
+            method: acquire_method(node),
+            headers: acquire_headers(node),
+            body: node.http_body.as_deref(),
+        }
+    }
+
+    pub fn redirect(&self, status_code: u16, location: &str) -> Result<HTTPRequest<'a>, ()> {
+        let url_current = Url::parse(&self.url).map_err(|_| ())?;
+
+        // Notice: the location may be relative to the current URL (eg. '/login').
+        let url_next = url_current.join(location).map_err(|_| {
+            debug!("prober poll got invalid http redirect location");
+        })?;
+
+        if url_next.scheme() != "http" && url_next.scheme() != "https" {
+            debug!(
+                "prober poll got http redirect to unsupported scheme: {}",
+                url_next.scheme()
+            );
+
+            return Err(());
+        }
+
+        // Notice: '301', '302' and '303' redirects are followed with a 'GET' (without body), as \
+        //   done by browsers, while '307' and '308' redirects keep the method and body as-is.
+        let (method, body) = match (status_code, self.method) {
+            (301..=303, HTTPMethod::Head) => (HTTPMethod::Head, None),
+            (301..=303, _) => (HTTPMethod::Get, None),
+            _ => (self.method, self.body),
+        };
+
+        // Notice: custom headers and credentials are not sent to another origin, as they would \
+        //   otherwise leak to whatever host the redirect points to.
+        let headers = if url_next.origin() == url_current.origin() {
+            self.headers.clone()
+        } else {
+            Vec::new()
+        };
+
+        Ok(HTTPRequest {
+            url: url_next.into(),
+            method,
+            headers,
+            body,
+        })
+    }
+}
+
+impl HTTPResponse {
+    pub fn redirect_location(&self) -> Option<&str> {
+        match self.status_code {
+            301..=303 | 307 | 308 => self.location.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HTTPStatusRange {
+    fn deserialize<D>(de: D) -> Result<HTTPStatusRange, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct HTTPStatusRangeVisitor;
+
+        impl<'de> Visitor<'de> for HTTPStatusRangeVisitor {
+            type Value = HTTPStatusRange;
+
+            fn expecting(&self, format: &mut fmt::Formatter) -> fmt::Result {
+                format.write_str("an HTTP status code or an HTTP status code range")
+            }
+
+            fn visit_i64<E: Error>(self, value: i64) -> Result<HTTPStatusRange, E> {
+                HTTPStatusRange::parse_from(&value.to_string()).map_err(|_| E::custom("invalid"))
+            }
+
+            fn visit_u64<E: Error>(self, value: u64) -> Result<HTTPStatusRange, E> {
+                HTTPStatusRange::parse_from(&value.to_string()).map_err(|_| E::custom("invalid"))
+            }
+
+            fn visit_str<E: Error>(self, value: &str) -> Result<HTTPStatusRange, E> {
+                HTTPStatusRange::parse_from(value).map_err(|_| E::custom("invalid"))
+            }
+        }
+
+        de.deserialize_any(HTTPStatusRangeVisitor)
+    }
+}
+
+fn acquire_method(node: &ConfigProbeServiceNode) -> HTTPMethod {
+    match node.http_method {
+        Some(method) => method,
+        None => {
+            // Notice: a body is required to run body matchers, which 'HEAD' does not return, \
+            //   therefore default to 'GET' whenever a body matcher is configured.
+            if has_body_matchers(node) {
+                HTTPMethod::Get
+            } else {
+                HTTPMethod::Head
+            }
+        }
+    }
+}
+
+pub fn acquire_headers(node: &ConfigProbeServiceNode) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+
+    if let Some(ref http_headers) = node.http_headers {
+        for (header_name, header_value) in http_headers {
+            headers.push((header_name.to_owned(), header_value.to_owned()));
+        }
+    }
+
+    // Notice: authentication is appended last, so that it overrides any 'Authorization' header \
+    //   that would have been configured in the custom headers.
+    if let Some(ref http_auth) = node.http_auth {
+        headers.push(http_auth.as_header());
+    }
+
+    headers
+}
+
+pub fn request<S: Read + Write>(request: &HTTPRequest, stream: &mut S) -> Result<HTTPResponse, ()> {
+    let (method, headers) = (request.method, &request.headers);
+
+    let request_uri = Uri::try_from(request.url.as_str()).map_err(|_| {
+        error!(
+            "prober poll http url is invalid: {}",
+            ReplicaURL::redact(&request.url)
+        );
+    })?;
+
+    // Build request message
+    // Notice: the connection gets closed after the response is received, so that the \
+    //   response body can be read until the end of the stream if it has no known length.
+    let mut request_message = RequestMessage::new(&request_uri);
+
+    request_message
+        .method(method.as_method())
+        .header("User-Agent", &*POLL_HTTP_HEADER_USERAGENT)
+        .header("Connection", "close");
+
+    for (header_name, header_value) in headers {
+        request_message.header(header_name, header_value);
+    }
+
+    if let Some(body) = request.body {
+        request_message.body(body.as_bytes());
+    }
+
+    debug!(
+        "prober poll will send http request with method: {} ({} custom headers)",
+        method.as_str(),
+        headers.len()
+    );
+
+    // Send request message
+    stream
+        .write_all(&request_message.parse())
+        .and_then(|_| stream.flush())
+        .map_err(|err| {
+            debug!("prober poll could not send http request (error: {})", err);
+        })?;
+
+    // Receive response head
+    let mut reader = BufReader::new(stream);
+    let mut response_head = Vec::new();
+
+    loop {
+        let mut line = Vec::new();
+
+        match (&mut reader)
+            .take(HTTP_RESPONSE_HEAD_MAXIMUM_SIZE)
+            .read_until(b'\n', &mut line)
+        {
+            Ok(0) | Err(_) => {
+                debug!("prober poll did not receive a full http response head");
+
+                return Err(());
+            }
+            Ok(_) => {
+                response_head.extend(&line);
+
+                if line == b"\r\n" || line == b"\n" {
+                    break;
+                }
+            }
+        }
+    }
+
+    let response = Response::from_head(&response_head).map_err(|err| {
+        debug!(
+            "prober poll received an invalid http response (error: {})",
+            err
+        );
+    })?;
+
+    let status_code = u16::from(response.status_code());
+    let location = response
+        .headers()
+        .get("Location")
+        .map(|location| location.to_owned());
+
+    // Receive response body (if any)
+    let mut response_body = Vec::new();
+
+    let has_body = !matches!(method, HTTPMethod::Head)
+        && status_code >= 200
+        && status_code != 204
+        && status_code != 304;
+
+    if has_body {
+        let body_result = if response.is_chunked() {
+            ChunkReader::new(reader)
+                .take(HTTP_RESPONSE_BODY_MAXIMUM_SIZE)
+                .read_to_end(&mut response_body)
+        } else {
+            let body_size = response
+                .content_len()
+                .map(|size| size as u64)
+                .unwrap_or(HTTP_RESPONSE_BODY_MAXIMUM_SIZE);
+
+            reader
+                .take(body_size.min(HTTP_RESPONSE_BODY_MAXIMUM_SIZE))
+                .read_to_end(&mut response_body)
+        };
+
+        // Notice: some servers close TLS connections without sending a 'close_notify' alert, \
+        //   which gets reported as an unexpected EOF once the whole body has been received.
+        body_result
+            .or_else(|err| {
+                if err.kind() == ErrorKind::UnexpectedEof {
+                    Ok(0)
+                } else {
+                    Err(err)
+                }
+            })
+            .map_err(|err| {
+                debug!(
+                    "prober poll could not receive http response body (error: {})",
+                    err
+                );
+            })?;
+    }
+
+    Ok(HTTPResponse {
+        status_code,
+        body: response_body,
+        location,
+    })
+}
+
+pub fn evaluate(node: &ConfigProbeServiceNode, status_code: u16, body: &[u8]) -> Status {
+    // Check response status code (a status code outside of configured ranges is considered as \
+    //   sick, as the server is up, while the default range keeps considering it as dead)
+    let (status_accepted, status_rejected) = match node.http_status_healthy {
+        Some(ref ranges) => (
+            ranges.iter().any(|range| range.contains(status_code)),
+            Status::Sick,
+        ),
+        None => (
+            (HTTP_STATUS_HEALTHY_ABOVE..HTTP_STATUS_HEALTHY_BELOW).contains(&status_code),
+            Status::Dead,
+        ),
+    };
+
+    if !status_accepted {
+        debug!(
+            "prober poll got non-accepted http status code: {}",
+            status_code
+        );
+
+        return status_rejected;
+    }
+
+    // Check response body (a body mismatch is considered as sick, as the server is up)
+    if has_body_matchers(node) && !evaluate_body(node, body) {
+        debug!(
+            "prober poll got http status code: {} though body did not match",
+            status_code
+        );
+
+        return Status::Sick;
+    }
+
+    Status::Healthy
+}
+
+fn has_body_matchers(node: &ConfigProbeServiceNode) -> bool {
+    node.http_body_healthy_match.is_some()
+        || node.http_body_healthy_contains.is_some()
+        || node.http_body_healthy_json.is_some()
+}
+
+fn evaluate_body(node: &ConfigProbeServiceNode, body: &[u8]) -> bool {
+    let body_string = String::from_utf8_lossy(body);
+
+    // Notice: all configured matchers must match for the body to be considered as healthy.
+    if let Some(ref body_match) = node.http_body_healthy_match {
+        if !body_match.is_match(&body_string) {
+            debug!("prober poll http body did not match regex: {}", body_match);
+
+            return false;
+        }
+    }
+
+    if let Some(ref body_contains) = node.http_body_healthy_contains {
+        if !body_string.contains(body_contains.as_str()) {
+            debug!("prober poll http body does not contain: {}", body_contains);
+
+            return false;
+        }
+    }
+
+    if let Some(ref body_json) = node.http_body_healthy_json {
+        let value = serde_json::from_slice::<JSONValue>(body).ok();
+
+        let is_match = value
+            .as_ref()
+            .and_then(|value| value.pointer(&body_json.pointer))
+            .map(|value| value == &body_json.equals)
+            .unwrap_or(false);
+
+        if !is_match {
+            debug!(
+                "prober poll http body json value at: {} is not: {}",
+                body_json.pointer, body_json.equals
+            );
+
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::config::test_node;
+
+    fn range(raw_range: &str) -> Option<(u16, u16)> {
+        HTTPStatusRange::parse_from(raw_range)
+            .ok()
+            .map(|range| (range.0, range.1))
+    }
+
+    fn response(status_code: u16, location: Option<&str>) -> HTTPResponse {
+        HTTPResponse {
+            status_code,
+            body: Vec::new(),
+            location: location.map(|location| location.to_owned()),
+        }
+    }
+
+    #[test]
+    fn it_parses_status_ranges() {
+        assert_eq!(range("200"), Some((200, 200)));
+        assert_eq!(range("300-304"), Some((300, 304)));
+        assert_eq!(range(" 200 - 299 "), Some((200, 299)));
+        assert_eq!(range("304-300"), None);
+        assert_eq!(range("200-"), None);
+        assert_eq!(range("2xx"), None);
+        assert_eq!(range(""), None);
+    }
+
+    #[test]
+    fn it_deserializes_status_ranges_from_integers_and_strings() {
+        let node = test_node("poll", "http_status_healthy = [204, \"300-304\"]");
+        let ranges = node.http_status_healthy.unwrap();
+
+        assert_eq!(ranges.len(), 2);
+        assert!(ranges[0].contains(204));
+        assert!(!ranges[0].contains(205));
+        assert!(ranges[1].contains(300) && ranges[1].contains(304));
+        assert!(!ranges[1].contains(305));
+    }
+
+    #[test]
+    fn it_reports_dead_outside_of_default_status_range() {
+        let node = test_node("poll", "");
+
+        assert_eq!(evaluate(&node, 200, b""), Status::Healthy);
+        assert_eq!(evaluate(&node, 302, b""), Status::Healthy);
+        assert_eq!(evaluate(&node, 404, b""), Status::Dead);
+        assert_eq!(evaluate(&node, 503, b""), Status::Dead);
+    }
+
+    #[test]
+    fn it_reports_sick_outside_of_configured_status_ranges() {
+        let node = test_node("poll", "http_status_healthy = [\"200-299\", 401]");
+
+        assert_eq!(evaluate(&node, 204, b""), Status::Healthy);
+        assert_eq!(evaluate(&node, 401, b""), Status::Healthy);
+        assert_eq!(evaluate(&node, 302, b""), Status::Sick);
+        assert_eq!(evaluate(&node, 503, b""), Status::Sick);
+    }
+
+    #[test]
+    fn it_matches_body_against_regex() {
+        let node = test_node("poll", "http_body_healthy_match = \"^status: (ok|up)$\"");
+
+        assert_eq!(evaluate(&node, 200, b"status: up"), Status::Healthy);
+        assert_eq!(evaluate(&node, 200, b"status: down"), Status::Sick);
+    }
+
+    #[test]
+    fn it_matches_body_against_substring() {
+        let node = test_node("poll", "http_body_healthy_contains = \"ready\"");
+
+        assert_eq!(evaluate(&node, 200, b"service is ready"), Status::Healthy);
+        assert_eq!(evaluate(&node, 200, b"service is starting"), Status::Sick);
+    }
+
+    #[test]
+    fn it_matches_body_against_json_value() {
+        let node = test_node(
+            "poll",
+            "http_body_healthy_json = { pointer = \"/checks/0/ok\", equals = true }",
+        );
+
+        assert_eq!(
+            evaluate(&node, 200, br#"{"checks": [{"ok": true}]}"#),
+            Status::Healthy
+        );
+        assert_eq!(
+            evaluate(&node, 200, br#"{"checks": [{"ok": false}]}"#),
+            Status::Sick
+        );
+        assert_eq!(evaluate(&node, 200, br#"{"checks": []}"#), Status::Sick);
+        assert_eq!(evaluate(&node, 200, b"not json"), Status::Sick);
+    }
+
+    #[test]
+    fn it_requires_all_body_matchers_to_match() {
+        let node = test_node(
+            "poll",
+            "http_body_healthy_contains = \"ok\"\nhttp_body_healthy_match = \"^\\\\{\"",
+        );
+
+        assert_eq!(evaluate(&node, 200, br#"{"ok": 1}"#), Status::Healthy);
+        assert_eq!(evaluate(&node, 200, b"ok"), Status::Sick);
+    }
+
+    #[test]
+    fn it_defaults_to_get_upon_body_matchers() {
+        assert!(matches!(
+            acquire_method(&test_node("poll", "")),
+            HTTPMethod::Head
+        ));
+        assert!(matches!(
+            acquire_method(&test_node("poll", "http_body_healthy_contains = \"ok\"")),
+            HTTPMethod::Get
+        ));
+    }
+
+    #[test]
+    fn it_acquires_redirect_location_from_redirect_responses_only() {
+        assert_eq!(response(301, Some("/a")).redirect_location(), Some("/a"));
+        assert_eq!(response(308, Some("/a")).redirect_location(), Some("/a"));
+        assert_eq!(response(302, None).redirect_location(), None);
+        assert_eq!(response(304, Some("/a")).redirect_location(), None);
+        assert_eq!(response(200, Some("/a")).redirect_location(), None);
+    }
+
+    #[test]
+    fn it_redirects_to_relative_and_absolute_locations() {
+        let node = test_node("poll", "");
+        let request = HTTPRequest::new(&node, "http://example.com/a/b?c=d");
+
+        assert_eq!(
+            request.redirect(302, "/login").unwrap().url,
+            "http://example.com/login"
+        );
+        assert_eq!(
+            request.redirect(302, "e").unwrap().url,
+            "http://example.com/a/e"
+        );
+        assert_eq!(
+            request.redirect(301, "https://example.org/").unwrap().url,
+            "https://example.org/"
+        );
+    }
+
+    #[test]
+    fn it_rejects_redirects_to_unsupported_schemes() {
+        let node = test_node("poll", "");
+        let request = HTTPRequest::new(&node, "http://example.com/");
+
+        assert!(request.redirect(302, "ftp://example.com/").is_err());
+        assert!(request.redirect(302, "http://[invalid/").is_err());
+    }
+
+    #[test]
+    fn it_follows_see_other_redirects_with_get() {
+        let node = test_node("poll", "http_method = \"POST\"\nhttp_body = \"x\"");
+        let request = HTTPRequest::new(&node, "http://example.com/");
+
+        for status_code in [301, 302, 303] {
+            let redirected = request.redirect(status_code, "/next").unwrap();
+
+            assert!(matches!(redirected.method, HTTPMethod::Get));
+            assert_eq!(redirected.body, None);
+        }
+
+        // Notice: a 'HEAD' request stays a 'HEAD' request, as it has no body.
+        let node = test_node("poll", "http_method = \"HEAD\"");
+        let request = HTTPRequest::new(&node, "http://example.com/");
+
+        assert!(matches!(
+            request.redirect(302, "/next").unwrap().method,
+            HTTPMethod::Head
+        ));
+    }
+
+    #[test]
+    fn it_follows_temporary_and_permanent_redirects_as_is() {
+        let node = test_node("poll", "http_method = \"POST\"\nhttp_body = \"x\"");
+        let request = HTTPRequest::new(&node, "http://example.com/");
+
+        for status_code in [307, 308] {
+            let redirected = request.redirect(status_code, "/next").unwrap();
+
+            assert!(matches!(redirected.method, HTTPMethod::Post));
+            assert_eq!(redirected.body, Some("x"));
+        }
+    }
+
+    #[test]
+    fn it_drops_headers_upon_origin_change() {
+        let node = test_node(
+            "poll",
+            "http_headers = { X-Key = \"secret\" }\nhttp_auth = { type = \"bearer\", token = \"t\" }",
+        );
+        let request = HTTPRequest::new(&node, "http://example.com/");
+
+        assert_eq!(request.headers.len(), 2);
+
+        // Same origin (headers are kept)
+        assert_eq!(request.redirect(302, "/next").unwrap().headers.len(), 2);
+
+        // Other host, scheme or port (headers are dropped)
+        for location in [
+            "http://example.org/",
+            "https://example.com/",
+            "http://example.com:8080/",
+        ] {
+            assert!(request.redirect(302, location).unwrap().headers.is_empty());
+        }
+    }
+}
@@ -0,0 +1,394 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::status::Status;
+use crate::config::config::ConfigProbeServiceNode;
+
+const ICMP_PACKET_PAYLOAD: &[u8; 16] = b"vigil-local-ping";
+const ICMP_PACKET_HEADER_SIZE: usize = 8;
+const ICMP_PACKET_BUFFER_SIZE: usize = 1024;
+
+const ICMP_V4_ECHO_REQUEST: u8 = 8;
+const ICMP_V4_ECHO_REPLY: u8 = 0;
+const ICMP_V6_ECHO_REQUEST: u8 = 128;
+const ICMP_V6_ECHO_REPLY: u8 = 129;
+
+lazy_static! {
+    static ref ICMP_METHOD_V4: Option<ICMPMethod> = ICMPMethod::detect(Domain::IPV4);
+    static ref ICMP_METHOD_V6: Option<ICMPMethod> = ICMPMethod::detect(Domain::IPV6);
+}
+
+static ICMP_IDENTIFIER: AtomicU16 = AtomicU16::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ICMPMethod {
+    Datagram,
+    Raw,
+}
+
+impl ICMPMethod {
+    fn detect(domain: Domain) -> Option<ICMPMethod> {
+        // Notice: unprivileged datagram sockets are preferred (Linux only, allowed for the \
+        //   groups listed in 'net.ipv4.ping_group_range'), raw sockets require root or the \
+        //   'CAP_NET_RAW' capability.
+        [ICMPMethod::Datagram, ICMPMethod::Raw]
+            .iter()
+            .copied()
+            .find(|method| open_socket(domain, *method).is_ok())
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ICMPMethod::Datagram => "unprivileged datagram sockets",
+            ICMPMethod::Raw => "raw sockets",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ICMPStats {
+    pub sent: u16,
+    pub received: u16,
+    pub rtt_average: Option<Duration>,
+    pub rtt_maximum: Option<Duration>,
+    pub jitter: Option<Duration>,
+}
+
+impl ICMPStats {
+    pub fn loss_percent(&self) -> u8 {
+        if self.sent == 0 {
+            100
+        } else {
+            (((self.sent - self.received) as u32 * 100) / self.sent as u32) as u8
+        }
+    }
+
+    pub fn merge(self, other: ICMPStats) -> ICMPStats {
+        // Notice: merged stats count packets over all addresses, and keep the highest \
+        //   round-trip-times and jitter (ie. those of the slowest address). Packet counts \
+        //   saturate, as many addresses could be pinged with a large count.
+        ICMPStats {
+            sent: self.sent.saturating_add(other.sent),
+            received: self.received.saturating_add(other.received),
+            rtt_average: self.rtt_average.max(other.rtt_average),
+            rtt_maximum: self.rtt_maximum.max(other.rtt_maximum),
+            jitter: self.jitter.max(other.jitter),
+        }
+    }
+}
+
+pub fn initialize() {
+    // Detect which ICMP method can be used for each address family
+    for (family, method) in [("ipv4", *ICMP_METHOD_V4), ("ipv6", *ICMP_METHOD_V6)] {
+        match method {
+            Some(method) => info!("icmp pings over {} will use {}", family, method.as_str()),
+            None => error!(
+                "icmp pings over {} are not possible (no unprivileged datagram or raw sockets)",
+                family
+            ),
+        }
+    }
+}
+
+pub fn ping_address(
+    node: &ConfigProbeServiceNode,
+    address: IpAddr,
+    timeout: Duration,
+) -> ICMPStats {
+    let mut rtts: Vec<Duration> = Vec::new();
+
+    match acquire_socket(address) {
+        Ok((socket, method)) => {
+            // Notice: in datagram mode, the kernel overwrites the identifier with its own one, \
+            //   and only passes replies that match it back to this socket.
+            let identifier = (process::id() as u16)
+                .wrapping_add(ICMP_IDENTIFIER.fetch_add(1, Ordering::Relaxed));
+
+            for sequence in 0..node.icmp_count {
+                // Wait between pings (except before the first one)
+                if sequence > 0 {
+                    thread::sleep(Duration::from_millis(node.icmp_interval_ms));
+                }
+
+                match ping_once(&socket, method, address, identifier, sequence, timeout) {
+                    Ok(rtt) => rtts.push(rtt),
+                    Err(err) => {
+                        debug!(
+                            "prober poll lost icmp ping #{} to target: {} (error: {})",
+                            sequence, address, err
+                        );
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            warn!(
+                "prober poll could not open icmp socket for target: {} (error: {})",
+                address, err
+            );
+        }
+    }
+
+    // Compute round-trip-time stats (jitter is the mean difference between consecutive RTTs)
+    let jitter = if rtts.len() > 1 {
+        let jitter_total: Duration = rtts.windows(2).map(|pair| pair[1].abs_diff(pair[0])).sum();
+
+        Some(jitter_total / (rtts.len() - 1) as u32)
+    } else {
+        None
+    };
+
+    ICMPStats {
+        sent: node.icmp_count,
+        received: rtts.len() as u16,
+        rtt_average: if rtts.is_empty() {
+            None
+        } else {
+            Some(rtts.iter().sum::<Duration>() / rtts.len() as u32)
+        },
+        rtt_maximum: rtts.iter().max().copied(),
+        jitter,
+    }
+}
+
+fn ping_once(
+    socket: &Socket,
+    method: ICMPMethod,
+    address: IpAddr,
+    identifier: u16,
+    sequence: u16,
+    timeout: Duration,
+) -> Result<Duration, String> {
+    let (request_type, reply_type) = match address {
+        IpAddr::V4(_) => (ICMP_V4_ECHO_REQUEST, ICMP_V4_ECHO_REPLY),
+        IpAddr::V6(_) => (ICMP_V6_ECHO_REQUEST, ICMP_V6_ECHO_REPLY),
+    };
+
+    // Build echo request packet (the kernel computes ICMPv6 checksums by itself)
+    let mut packet = Vec::with_capacity(ICMP_PACKET_HEADER_SIZE + ICMP_PACKET_PAYLOAD.len());
+
+    packet.extend_from_slice(&[request_type, 0, 0, 0]);
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(ICMP_PACKET_PAYLOAD);
+
+    if address.is_ipv4() {
+        let checksum = checksum(&packet);
+
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    let start_time = Instant::now();
+
+    (&*socket)
+        .write_all(&packet)
+        .map_err(|err| format!("send failed: {}", err))?;
+
+    // Wait for matching echo reply (other packets might be received meanwhile)
+    let mut buffer = [0; ICMP_PACKET_BUFFER_SIZE];
+
+    loop {
+        let remaining = timeout.saturating_sub(start_time.elapsed());
+
+        if remaining.is_zero() {
+            return Err("timed out".to_string());
+        }
+
+        socket
+            .set_read_timeout(Some(remaining))
+            .map_err(|err| format!("could not set timeout: {}", err))?;
+
+        let read_size = match (&*socket).read(&mut buffer) {
+            Ok(read_size) => read_size,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(ref err)
+                if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+            {
+                return Err("timed out".to_string())
+            }
+            Err(err) => return Err(format!("receive failed: {}", err)),
+        };
+
+        // Notice: raw IPv4 sockets receive the IP header along with the ICMP packet, while \
+        //   datagram and raw IPv6 sockets only receive the ICMP packet.
+        let reply = if method == ICMPMethod::Raw && address.is_ipv4() {
+            let header_size = buffer.first().map(|byte| (byte & 0x0f) as usize * 4);
+
+            &buffer[header_size.unwrap_or(0).min(read_size)..read_size]
+        } else {
+            &buffer[..read_size]
+        };
+
+        if reply.len() >= ICMP_PACKET_HEADER_SIZE
+            && reply[0] == reply_type
+            && reply[6..8] == sequence.to_be_bytes()
+            && (method == ICMPMethod::Datagram || reply[4..6] == identifier.to_be_bytes())
+        {
+            return Ok(start_time.elapsed());
+        }
+    }
+}
+
+fn acquire_socket(address: IpAddr) -> Result<(Socket, ICMPMethod), String> {
+    let (domain, method) = match address {
+        IpAddr::V4(_) => (Domain::IPV4, *ICMP_METHOD_V4),
+        IpAddr::V6(_) => (Domain::IPV6, *ICMP_METHOD_V6),
+    };
+
+    let method = method.ok_or_else(|| "no icmp method available".to_string())?;
+    let socket = open_socket(domain, method).map_err(|err| err.to_string())?;
+
+    // Notice: connecting the socket makes the kernel drop packets from other addresses.
+    socket
+        .connect(&SocketAddr::new(address, 0).into())
+        .map_err(|err| format!("connect failed: {}", err))?;
+
+    Ok((socket, method))
+}
+
+fn open_socket(domain: Domain, method: ICMPMethod) -> std::io::Result<Socket> {
+    let socket_type = match method {
+        ICMPMethod::Datagram => Type::DGRAM,
+        ICMPMethod::Raw => Type::RAW,
+    };
+
+    let protocol = if domain == Domain::IPV6 {
+        Protocol::ICMPV6
+    } else {
+        Protocol::ICMPV4
+    };
+
+    Socket::new(domain, socket_type, Some(protocol))
+}
+
+fn checksum(packet: &[u8]) -> u16 {
+    let mut sum = packet
+        .chunks(2)
+        .map(|chunk| u16::from_be_bytes([chunk[0], *chunk.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+pub fn evaluate(node: &ConfigProbeServiceNode, stats: &ICMPStats) -> Status {
+    let loss_percent = stats.loss_percent();
+
+    // Check packet loss (all packets lost is always dead)
+    if stats.received == 0 || loss_percent >= node.icmp_loss_dead_percent {
+        return Status::Dead;
+    }
+
+    if loss_percent >= node.icmp_loss_sick_percent {
+        return Status::Sick;
+    }
+
+    // Check jitter (if a threshold is set)
+    if let (Some(icmp_jitter_sick_ms), Some(jitter)) = (node.icmp_jitter_sick_ms, stats.jitter) {
+        if jitter >= Duration::from_millis(icmp_jitter_sick_ms) {
+            return Status::Sick;
+        }
+    }
+
+    // Check maximum round-trip-time (if a threshold is set; the average round-trip-time is \
+    //   checked against 'poll_delay_sick', as the replica latency)
+    if let (Some(icmp_rtt_max_sick_ms), Some(rtt_maximum)) =
+        (node.icmp_rtt_max_sick_ms, stats.rtt_maximum)
+    {
+        if rtt_maximum >= Duration::from_millis(icmp_rtt_max_sick_ms) {
+            return Status::Sick;
+        }
+    }
+
+    Status::Healthy
+}
+
+pub fn validate(node: &ConfigProbeServiceNode) -> Result<(), String> {
+    if node.icmp_count == 0 {
+        return Err("icmp_count must be at least 1".to_string());
+    }
+
+    if node.icmp_loss_sick_percent > 100 || node.icmp_loss_dead_percent > 100 {
+        return Err("icmp loss thresholds must be percentages".to_string());
+    }
+
+    if node.icmp_loss_sick_percent > node.icmp_loss_dead_percent {
+        return Err("icmp_loss_sick_percent must not be above icmp_loss_dead_percent".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::test_node;
+
+    fn stats(sent: u16, received: u16, rtt_average: u64, rtt_maximum: u64) -> ICMPStats {
+        ICMPStats {
+            sent,
+            received,
+            rtt_average: Some(Duration::from_millis(rtt_average)),
+            rtt_maximum: Some(Duration::from_millis(rtt_maximum)),
+            jitter: None,
+        }
+    }
+
+    #[test]
+    fn it_merges_stats_without_overflowing() {
+        let merged = stats(u16::MAX, u16::MAX - 1, 10, 20).merge(stats(5, 5, 30, 15));
+
+        assert_eq!(merged.sent, u16::MAX);
+        assert_eq!(merged.received, u16::MAX);
+        assert_eq!(merged.rtt_average, Some(Duration::from_millis(30)));
+        assert_eq!(merged.rtt_maximum, Some(Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn it_evaluates_maximum_rtt() {
+        let threshold_node = test_node("poll", "icmp_rtt_max_sick_ms = 100");
+
+        assert_eq!(
+            evaluate(&threshold_node, &stats(5, 5, 10, 99)),
+            Status::Healthy
+        );
+        assert_eq!(
+            evaluate(&threshold_node, &stats(5, 5, 10, 100)),
+            Status::Sick
+        );
+
+        // Maximum round-trip-time is ignored if no threshold is set
+        assert_eq!(
+            evaluate(&test_node("poll", ""), &stats(5, 5, 10, 5000)),
+            Status::Healthy
+        );
+    }
+
+    #[test]
+    fn it_evaluates_loss() {
+        let node = test_node(
+            "poll",
+            "icmp_loss_sick_percent = 20\nicmp_loss_dead_percent = 60",
+        );
+
+        assert_eq!(evaluate(&node, &stats(5, 5, 10, 10)), Status::Healthy);
+        assert_eq!(evaluate(&node, &stats(5, 4, 10, 10)), Status::Sick);
+        assert_eq!(evaluate(&node, &stats(5, 2, 10, 10)), Status::Dead);
+        assert_eq!(evaluate(&node, &stats(5, 0, 10, 10)), Status::Dead);
+    }
+}
@@ -4,53 +4,229 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use super::icmp::initialize as icmp_initialize;
 use super::poll::dispatch as poll_dispatch;
+use super::pool::Pool;
+use super::replica::ReplicaURL;
 use super::script::dispatch as script_dispatch;
+use super::states::{complete as states_complete, register as states_register};
+use crate::config::config::{ConfigProbeService, ConfigProbeServiceNode};
 use crate::probe::mode::Mode;
+
 use crate::APP_CONF;
 
 const PROBE_RUN_HOLD_SECONDS: u64 = 2;
 
+struct NodeSchedule {
+    service: &'static ConfigProbeService,
+    node: &'static ConfigProbeServiceNode,
+    interval: u64,
+    next_run: Instant,
+    pending_jobs: Arc<AtomicUsize>,
+}
+
+struct NodeJob {
+    service: &'static ConfigProbeService,
+    node: &'static ConfigProbeServiceNode,
+    pending_jobs: Arc<AtomicUsize>,
+    start_time: Instant,
+}
+
 pub fn run() {
     // Hold on a bit before first cycle
     thread::sleep(Duration::from_secs(PROBE_RUN_HOLD_SECONDS));
 
+    // Detect ICMP method (only if any ICMP replica is configured)
+    if has_icmp_replicas() {
+        icmp_initialize();
+    }
+
+    // Spawn worker pool (shared across all node runs)
+    let pool = Pool::new(APP_CONF.metrics.max_concurrency);
+
+    // Build node schedules (all nodes are due right away)
+    let mut schedules = Vec::new();
+    let start_time = Instant::now();
+
+    for service in &APP_CONF.probe.service {
+        for node in &service.node {
+            let interval = acquire_interval(service, node);
+
+            states_register(&service.id, &node.id, interval);
+
+            debug!(
+                "scheduling probe for service node: #{}:#{} every {}s",
+                service.id, node.id, interval
+            );
+
+            schedules.push(NodeSchedule {
+                service,
+                node,
+                interval,
+                next_run: start_time,
+                pending_jobs: Arc::new(AtomicUsize::new(0)),
+            });
+        }
+    }
+
     debug!("will run first probe cycle");
 
     // Start cycling
     loop {
-        cycle();
+        let now = Instant::now();
 
-        info!(
-            "done cycling probe, holding for next cycle: {}s",
-            APP_CONF.metrics.interval
-        );
+        for schedule in schedules.iter_mut() {
+            if schedule.next_run <= now {
+                tick(&pool, schedule);
 
-        // Hold for next aggregate run
-        thread::sleep(Duration::from_secs(APP_CONF.metrics.interval));
+                // Schedule next run (skip missed runs if they are already late)
+                schedule.next_run += Duration::from_secs(schedule.interval);
 
-        debug!("holding for next probe cycle, will run next cycle");
+                if schedule.next_run <= now {
+                    schedule.next_run = now + Duration::from_secs(schedule.interval);
+                }
+            }
+        }
+
+        // Hold until next node is due
+        let next_run = schedules
+            .iter()
+            .map(|schedule| schedule.next_run)
+            .min()
+            .unwrap_or(now + Duration::from_secs(APP_CONF.metrics.interval));
+
+        let hold_duration = next_run.saturating_duration_since(Instant::now());
+
+        debug!(
+            "done scheduling probe, holding for next node: {}ms",
+            hold_duration.as_millis()
+        );
+
+        thread::sleep(hold_duration);
     }
 }
 
-fn cycle() {
-    debug!("cycling through all services");
+fn has_icmp_replicas() -> bool {
+    APP_CONF.probe.service.iter().any(|service| {
+        service.node.iter().any(|node| {
+            node.replicas
+                .iter()
+                .flatten()
+                .any(|replica| matches!(replica, ReplicaURL::ICMP(..)))
+        })
+    })
+}
+
+fn acquire_interval(service: &ConfigProbeService, node: &ConfigProbeServiceNode) -> u64 {
+    // Notice: the most specific interval wins (node, then service, then global)
+    node.interval
+        .or(service.interval)
+        .unwrap_or(APP_CONF.metrics.interval)
+}
 
-    for service in &APP_CONF.probe.service {
-        debug!("scanning for nodes in service: #{}", service.id);
+fn tick(pool: &Pool, schedule: &NodeSchedule) {
+    let (service, node, interval) = (schedule.service, schedule.node, schedule.interval);
 
-        for node in &service.node {
-            debug!("scanning for targets in service node: #{}", node.id);
+    // Previous run still in progress? (the node overran its interval)
+    let pending_jobs = schedule.pending_jobs.load(Ordering::SeqCst);
+
+    if pending_jobs > 0 {
+        warn!(
+            "probe run overran interval for service node: #{}:#{} ({} jobs pending, interval is \
+                {}s), skipping this run",
+            service.id, node.id, pending_jobs, interval
+        );
+
+        return;
+    }
+
+    debug!(
+        "scanning for targets in service node: #{}:#{}",
+        service.id, node.id
+    );
+
+    // Notice: each replica is submitted as its own job to the worker pool, so that a slow \
+    //   replica (or a slow report) does not hold all other replicas. Each job decrements the \
+    //   node pending jobs counter once dropped (even if it panicked), which lets the next run \
+    //   detect overruns.
+    let start_time = Instant::now();
+
+    match node.mode {
+        Mode::Poll => match node.replicas {
+            Some(ref replicas) if !replicas.is_empty() => {
+                debug!("poll node has replicas in service node: #{}", node.id);
+
+                schedule
+                    .pending_jobs
+                    .store(replicas.len(), Ordering::SeqCst);
+
+                for replica in replicas {
+                    let job = NodeJob::new(schedule, start_time);
 
-            match node.mode {
-                Mode::Poll => poll_dispatch(service, node, APP_CONF.metrics.interval),
-                Mode::Script => script_dispatch(service, node, APP_CONF.metrics.interval),
+                    pool.execute(move || {
+                        poll_dispatch(service, node, replica, interval);
+
+                        drop(job);
+                    });
+                }
             }
+            _ => warn!(
+                "poll node has no usable replica in service node: #{}",
+                node.id
+            ),
+        },
+        Mode::Script => match node.scripts {
+            Some(ref scripts) if !scripts.is_empty() => {
+                debug!("script node has scripts in service node: #{}", node.id);
+
+                schedule.pending_jobs.store(scripts.len(), Ordering::SeqCst);
+
+                for (index, script) in scripts.iter().enumerate() {
+                    let job = NodeJob::new(schedule, start_time);
+
+                    pool.execute(move || {
+                        script_dispatch(service, node, index, script, interval);
+
+                        drop(job);
+                    });
+                }
+            }
+            _ => warn!(
+                "script node has no usable script in service node: #{}",
+                node.id
+            ),
+        },
+    }
+}
+
+impl NodeJob {
+    fn new(schedule: &NodeSchedule, start_time: Instant) -> NodeJob {
+        NodeJob {
+            service: schedule.service,
+            node: schedule.node,
+            pending_jobs: schedule.pending_jobs.clone(),
+            start_time,
         }
     }
+}
 
-    info!("done cycling through all services");
+impl Drop for NodeJob {
+    fn drop(&mut self) {
+        // Last job for this node run? (the run is done)
+        if self.pending_jobs.fetch_sub(1, Ordering::SeqCst) == 1 {
+            states_complete(&self.service.id, &self.node.id, self.start_time.elapsed());
+
+            info!(
+                "done probing service node: #{}:#{} (took {}ms)",
+                self.service.id,
+                self.node.id,
+                self.start_time.elapsed().as_millis()
+            );
+        }
+    }
 }
@@ -4,11 +4,25 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-mod status;
+pub mod status;
 
+pub mod database;
+pub mod dns;
+pub mod grpc;
+pub mod hpack;
+pub mod http;
+pub mod icmp;
 pub mod manager;
 pub mod mode;
 pub mod poll;
+pub mod pool;
 pub mod replica;
 pub mod report;
+pub mod resolve;
 pub mod script;
+pub mod spool;
+pub mod states;
+pub mod tcp;
+pub mod tls;
+pub mod udp;
+pub mod websocket;
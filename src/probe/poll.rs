@@ -4,128 +4,179 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-use http_req::{
-    request::{Method, Request},
-    uri::Uri,
-};
-use ping::ping;
-
 use std::cmp::min;
-use std::convert::TryFrom;
-use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixStream;
 use std::thread;
-use std::time::Duration;
 use std::time::SystemTime;
+use std::time::{Duration, Instant};
+
+use hickory_proto::op::Message as DNSMessage;
+use url::Url;
 
+use super::database::{
+    query_mysql as database_query_mysql, query_postgres as database_query_postgres,
+};
+use super::dns::{
+    decode as dns_decode, evaluate as dns_evaluate, exchange as dns_exchange, DNSQuery,
+};
+use super::grpc::{
+    evaluate as grpc_evaluate, request as grpc_request, GRPCResponse, GRPC_ALPN_PROTOCOL,
+};
+use super::http::{
+    evaluate as http_evaluate, request as http_request, HTTPRequest, HTTPResponse,
+    HTTP_REDIRECT_MAXIMUM,
+};
+use super::icmp::{evaluate as icmp_evaluate, ping_address as icmp_ping_address, ICMPStats};
 use super::replica::ReplicaURL;
 use super::report::{status as report_status, ReportReplica};
+use super::resolve::{probe as resolve_probe, resolve, ResolveOptions};
+use super::states::{
+    record as states_record, record_addresses as states_record_addresses,
+    record_icmp as states_record_icmp,
+};
 use super::status::Status;
+use super::tcp::{
+    evaluate as tcp_evaluate, exchange as tcp_exchange, has_exchange as tcp_has_exchange,
+    TCPProtocol,
+};
+use super::tls::{handshake as tls_handshake, handshake_alpn as tls_handshake_alpn};
+use super::udp::{acquire_payload as udp_acquire_payload, evaluate as udp_evaluate};
+use super::websocket::{
+    evaluate as websocket_evaluate, request as websocket_request, WebSocketResponse,
+};
 use crate::config::config::{ConfigProbeService, ConfigProbeServiceNode};
 use crate::APP_CONF;
 
 const RETRY_REPLICA_AFTER_MILLISECONDS: u64 = 200;
 const NODE_ICMP_TIMEOUT_SECONDS: u64 = 1;
 
-const HTTP_STATUS_HEALTHY_ABOVE: u16 = 200;
-const HTTP_STATUS_HEALTHY_BELOW: u16 = 400;
-
-lazy_static! {
-    static ref POLL_HTTP_HEADER_USERAGENT: String =
-        format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-}
-
-pub fn dispatch(service: &ConfigProbeService, node: &ConfigProbeServiceNode, interval: u64) {
-    if let Some(ref replicas) = node.replicas {
-        if !replicas.is_empty() {
-            debug!("poll node has replicas in service node: #{}", node.id);
-
-            for replica in replicas {
-                let replica_status = proceed_replica(&service.id, &node.id, replica);
+pub fn dispatch(
+    service: &ConfigProbeService,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+    interval: u64,
+) {
+    let (replica_status, replica_latency) = proceed_replica(&service.id, node, replica);
 
-                if replica_status == Status::Dead {
-                    warn!("got replica status upon poll: {:?}", replica_status);
-                } else {
-                    debug!("got replica status upon poll: {:?}", replica_status);
-                }
+    if replica_status == Status::Dead {
+        warn!("got replica status upon poll: {:?}", replica_status);
+    } else {
+        debug!("got replica status upon poll: {:?}", replica_status);
+    }
 
-                match report_status(
-                    &service,
-                    node,
-                    ReportReplica::Poll(replica),
-                    &replica_status,
-                    interval,
-                ) {
-                    Ok(_) => info!("reported poll replica status: {:?}", replica_status),
-                    Err(_) => error!("failed reporting poll replica status: {:?}", replica_status),
-                }
-            }
+    let report_result = report_status(
+        service,
+        node,
+        ReportReplica::Poll(replica),
+        &replica_status,
+        interval,
+    );
 
-            return;
-        }
+    match report_result {
+        Ok(_) => info!("reported poll replica status: {:?}", replica_status),
+        Err(_) => error!("failed reporting poll replica status: {:?}", replica_status),
     }
 
-    warn!(
-        "poll node has no usable replica in service node: #{}",
-        node.id
+    states_record(
+        &service.id,
+        &node.id,
+        &ReportReplica::Poll(replica).as_state_id(),
+        &replica_status,
+        replica_latency,
+        None,
+        report_result.is_ok(),
     );
 }
 
-pub fn proceed_replica(service_id: &str, node_id: &str, replica: &ReplicaURL) -> Status {
+pub fn proceed_replica(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+) -> (Status, Option<Duration>) {
     // Attempt to acquire (first attempt)
-    proceed_replica_attempt(service_id, node_id, replica, APP_CONF.metrics.poll_retry, 0)
+    proceed_replica_attempt(service_id, node, replica, APP_CONF.metrics.poll_retry, 0)
 }
 
 fn proceed_replica_attempt(
     service_id: &str,
-    node_id: &str,
+    node: &ConfigProbeServiceNode,
     replica: &ReplicaURL,
     retry_times: u8,
     attempt: u8,
-) -> Status {
+) -> (Status, Option<Duration>) {
     info!(
         "running poll replica scan attempt #{} on #{}:#{}:[{:?}]",
-        attempt, service_id, node_id, replica
+        attempt, service_id, node.id, replica
     );
 
-    match proceed_replica_request(service_id, node_id, replica) {
-        Status::Healthy => Status::Healthy,
-        Status::Sick => Status::Sick,
-        Status::Dead => {
+    match proceed_replica_request(service_id, node, replica) {
+        (Status::Healthy, latency) => (Status::Healthy, latency),
+        (Status::Sick, latency) => (Status::Sick, latency),
+        (Status::Dead, latency) => {
             let next_attempt = attempt + 1;
 
             if next_attempt > retry_times {
-                Status::Dead
+                (Status::Dead, latency)
             } else {
                 warn!(
                     "poll replica scan attempt #{} failed on #{}:#{}:[{:?}], will retry",
-                    attempt, service_id, node_id, replica
+                    attempt, service_id, node.id, replica
                 );
 
                 // Retry after delay
                 thread::sleep(Duration::from_millis(RETRY_REPLICA_AFTER_MILLISECONDS));
 
-                proceed_replica_attempt(service_id, node_id, replica, retry_times, next_attempt)
+                proceed_replica_attempt(service_id, node, replica, retry_times, next_attempt)
             }
         }
     }
 }
 
-fn proceed_replica_request(service_id: &str, node_id: &str, replica: &ReplicaURL) -> Status {
+fn proceed_replica_request(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+) -> (Status, Option<Duration>) {
     debug!(
         "scanning poll replica: #{}:#{}:[{:?}]",
-        service_id, node_id, replica
+        service_id, node.id, replica
     );
 
     let start_time = SystemTime::now();
 
-    let (is_up, poll_duration) = match replica {
-        &ReplicaURL::ICMP(_, ref host) => proceed_replica_request_icmp(host),
-        &ReplicaURL::TCP(_, ref host, port) => proceed_replica_request_tcp(host, port),
-        &ReplicaURL::HTTP(_, ref url) => proceed_replica_request_http(url),
-        &ReplicaURL::HTTPS(_, ref url) => proceed_replica_request_http(url),
+    let (status, poll_duration) = match replica {
+        ReplicaURL::ICMP(_, host, options) => {
+            proceed_replica_request_icmp(service_id, node, replica, host, options)
+        }
+        ReplicaURL::TCP(_, host, port, protocol, options) => {
+            proceed_replica_request_tcp(service_id, node, replica, host, *port, *protocol, options)
+        }
+        ReplicaURL::TLS(_, host, port) => proceed_replica_request_tls(node, host, *port),
+        ReplicaURL::UDP(_, host, port) => proceed_replica_request_udp(node, host, *port),
+        ReplicaURL::DNS(_, host, port, query) => proceed_replica_request_dns(host, *port, query),
+        ReplicaURL::GRPC(_, host, port, service) => {
+            proceed_replica_request_grpc(node, host, *port, service, false)
+        }
+        ReplicaURL::GRPCS(_, host, port, service) => {
+            proceed_replica_request_grpc(node, host, *port, service, true)
+        }
+        ReplicaURL::Postgres(_, url) => proceed_replica_request_postgres(node, url),
+        ReplicaURL::MySQL(_, url) => proceed_replica_request_mysql(node, url),
+        ReplicaURL::Unix(_, path) => proceed_replica_request_unix(node, path),
+        ReplicaURL::HTTP(_, url, options) => {
+            proceed_replica_request_http(service_id, node, replica, url, options)
+        }
+        ReplicaURL::HTTPS(_, url, options) => {
+            proceed_replica_request_http(service_id, node, replica, url, options)
+        }
+        ReplicaURL::HTTPUnix(_, path, url) => proceed_replica_request_http_unix(node, path, url),
+        ReplicaURL::WS(_, url) => proceed_replica_request_websocket(node, url),
+        ReplicaURL::WSS(_, url) => proceed_replica_request_websocket(node, url),
     };
 
-    if is_up == true {
+    if status != Status::Dead {
         // Acquire poll duration latency
         let duration_latency = match poll_duration {
             Some(poll_duration) => poll_duration,
@@ -135,176 +186,784 @@ fn proceed_replica_request(service_id: &str, node_id: &str, replica: &ReplicaURL
         };
 
         if duration_latency >= Duration::from_secs(APP_CONF.metrics.poll_delay_sick) {
-            Status::Sick
+            (Status::Sick, Some(duration_latency))
         } else {
-            Status::Healthy
+            (status, Some(duration_latency))
         }
     } else {
-        Status::Dead
+        (Status::Dead, None)
     }
 }
 
-fn proceed_replica_request_icmp(host: &str) -> (bool, Option<Duration>) {
+fn proceed_replica_request_icmp(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+    host: &str,
+    options: &ResolveOptions,
+) -> (Status, Option<Duration>) {
     // Notice: a dummy port of value '0' is set here, so that we can resolve the host to an actual \
     //   IP address using the standard library, which avoids depending on an additional library.
-    let address_results = (host, 0).to_socket_addrs();
+    let address_values = match resolve(host, 0, options) {
+        Ok(address_values) => address_values,
+        Err(_) => {
+            // Consider unresolved host as a failure
+            return (Status::Dead, None);
+        }
+    };
 
-    // Storage variable for the maximum round-trip-time found for received ping responses
-    let mut maximum_rtt = None;
+    debug!(
+        "prober poll will fire for icmp host: {} ({} targets, {} pings each)",
+        host,
+        address_values.len(),
+        node.icmp_count
+    );
 
-    match address_results {
-        Ok(address) => {
-            // Notice: the ICMP probe checker is a bit special, in the sense that it checks all \
-            //   resolved addresses. As we check for an host health at the IP level (ie. not at \
-            //   the application layer level), checking only the first host in the list is not \
-            //   sufficient for the whole replica group to be up. This can be used as an handy way \
-            //   to check for the health of a group of IP hosts, configured in a single DNS record.
-            let address_values: Vec<SocketAddr> = address.collect();
+    // As ICMP pings require a lower-than-usual timeout, an hard-coded ICMP timeout value is \
+    //   used by default, though the configured dead delay value is preferred in the event it \
+    //   is lower than the hard-coded value (unlikely though possible in some setups).
+    let pinger_timeout = match node.icmp_timeout_ms {
+        Some(icmp_timeout_ms) => Duration::from_millis(icmp_timeout_ms),
+        None => Duration::from_secs(min(
+            NODE_ICMP_TIMEOUT_SECONDS,
+            acquire_dead_timeout().as_secs(),
+        )),
+    };
 
-            if !address_values.is_empty() {
-                debug!(
-                    "prober poll will fire for icmp host: {} ({} targets)",
-                    host,
-                    address_values.len()
-                );
+    // Notice: the ICMP probe checker is a bit special, in the sense that it checks all resolved \
+    //   addresses by default. As we check for an host health at the IP level (ie. not at the \
+    //   application layer level), checking only the first host in the list is not sufficient \
+    //   for the whole replica group to be up. This can be used as an handy way to check for the \
+    //   health of a group of IP hosts, configured in a single DNS record.
+    let mut replica_stats: Option<ICMPStats> = None;
 
-                // As ICMP pings require a lower-than-usual timeout, an hard-coded ICMP \
-                //   timeout value is used by default, though the configured dead delay value \
-                //   is preferred in the event it is lower than the hard-coded value (unlikely \
-                //   though possible in some setups).
-                let pinger_timeout = Duration::from_secs(min(
-                    NODE_ICMP_TIMEOUT_SECONDS,
-                    acquire_dead_timeout().as_secs(),
-                ));
+    let result = resolve_probe(&address_values, options, |address_value| {
+        let address_ip = address_value.ip();
 
-                // Probe all returned addresses (sequentially)
-                for address_value in &address_values {
-                    let address_ip = address_value.ip();
+        debug!(
+            "prober poll will send icmp pings to target: {} from host: {}",
+            address_ip, host
+        );
+
+        let stats = icmp_ping_address(node, address_ip, pinger_timeout);
+
+        info!(
+            "prober poll got icmp stats for target: {} from host: {} (sent: {}, received: {}, \
+                loss: {}%, rtt avg: {:?}, rtt max: {:?}, jitter: {:?})",
+            address_ip,
+            host,
+            stats.sent,
+            stats.received,
+            stats.loss_percent(),
+            stats.rtt_average,
+            stats.rtt_maximum,
+            stats.jitter
+        );
+
+        replica_stats = Some(match replica_stats {
+            Some(replica_stats) => replica_stats.merge(stats),
+            None => stats,
+        });
+
+        // Use the average round-trip-time as the address latency
+        (icmp_evaluate(node, &stats), stats.rtt_average)
+    });
+
+    if let Some(ref replica_stats) = replica_stats {
+        states_record_icmp(
+            service_id,
+            &node.id,
+            &ReportReplica::Poll(replica).as_state_id(),
+            replica_stats,
+        );
+    }
 
+    record_failed_addresses(service_id, node, replica, host, &result.failed);
+
+    (result.status, result.latency)
+}
+
+fn proceed_replica_request_tcp(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+    host: &str,
+    port: u16,
+    protocol: Option<TCPProtocol>,
+    options: &ResolveOptions,
+) -> (Status, Option<Duration>) {
+    let address_values = match resolve(host, port, options) {
+        Ok(address_values) => address_values,
+        Err(_) => return (Status::Dead, None),
+    };
+
+    let result = resolve_probe(&address_values, options, |address_value| {
+        match connect_tcp_stream(address_value) {
+            Some(mut stream) => {
+                // Notice: a connection being accepted does not prove that the service is \
+                //   responsive (eg. a wedged process), therefore exchange with it if a protocol \
+                //   or a payload / banner is configured.
+                if tcp_has_exchange(node, protocol) {
                     debug!(
-                        "prober poll will send icmp ping to target: {} from host: {}",
-                        address_ip, host
+                        "prober poll will exchange with tcp target: {} (protocol: {:?})",
+                        address_value, protocol
                     );
 
-                    // Acquire ping start time (used for RTT calculation)
-                    let ping_start_time = SystemTime::now();
-
-                    // Ping target IP address
-                    match ping(address_ip, Some(pinger_timeout), None, None, None, None) {
-                        Ok(_) => {
-                            debug!(
-                                "got prober poll response for icmp target: {} from host: {}",
-                                address_ip, host
-                            );
-
-                            // Process ping RTT
-                            let ping_rtt = SystemTime::now()
-                                .duration_since(ping_start_time)
-                                .unwrap_or(Duration::from_secs(0));
-
-                            // Do not return (consider address as reachable)
-                            // Notice: update maximum observed round-trip-time, if higher than \
-                            //   last highest observed.
-                            maximum_rtt = match maximum_rtt {
-                                Some(maximum_rtt) => {
-                                    if ping_rtt > maximum_rtt {
-                                        Some(ping_rtt)
-                                    } else {
-                                        Some(maximum_rtt)
-                                    }
-                                }
-                                None => Some(ping_rtt),
-                            };
-                        }
-                        Err(err) => {
-                            debug!(
-                                "prober poll error for icmp target: {} from host: {} (error: {})",
-                                address_ip, host, err
-                            );
-
-                            // Consider ICMP errors as a failure
-                            return (false, None);
-                        }
+                    match tcp_exchange(node, protocol, &mut stream) {
+                        Ok(banner) => (tcp_evaluate(node, protocol, &banner), None),
+                        Err(_) => (Status::Dead, None),
                     }
+                } else {
+                    (Status::Healthy, None)
                 }
+            }
+            None => (Status::Dead, None),
+        }
+    });
+
+    record_failed_addresses(service_id, node, replica, host, &result.failed);
+
+    (result.status, result.latency)
+}
+
+fn proceed_replica_request_udp(
+    node: &ConfigProbeServiceNode,
+    host: &str,
+    port: u16,
+) -> (Status, Option<Duration>) {
+    debug!("prober poll will fire for udp target: {}:{}", host, port);
+
+    let socket = match acquire_udp_socket(host, port) {
+        Some(socket) => socket,
+        None => return (Status::Dead, None),
+    };
+
+    // Send payload (an empty datagram is sent if no payload is configured)
+    if let Err(err) = socket.send(udp_acquire_payload(node)) {
+        debug!(
+            "prober poll could not send udp payload to target: {}:{} (error: {})",
+            host, port, err
+        );
+
+        return (Status::Dead, None);
+    }
+
+    // Acquire response datagram
+    let mut response = [0; 65536];
+
+    match socket.recv(&mut response) {
+        Ok(response_size) => {
+            debug!(
+                "prober poll got udp response from target: {}:{} ({} bytes)",
+                host, port, response_size
+            );
+
+            (udp_evaluate(node, &response[..response_size]), None)
+        }
+        Err(ref err)
+            if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+        {
+            // Notice: fire-and-forget services never respond, therefore a timeout can be \
+            //   considered as healthy for those, as long as the target port is not closed. The \
+            //   latency is zeroed, as the time spent waiting does not reflect the replica latency.
+            if node.udp_timeout_healthy {
+                debug!(
+                    "prober poll got no udp response from target: {}:{}, considering healthy",
+                    host, port
+                );
+
+                (Status::Healthy, Some(Duration::from_secs(0)))
             } else {
                 debug!(
-                    "prober poll did not resolve any address for icmp replica: {}",
-                    host
+                    "prober poll got no udp response from target: {}:{}",
+                    host, port
                 );
 
-                // Consider empty as a failure
-                return (false, None);
+                (Status::Dead, None)
             }
         }
         Err(err) => {
-            error!(
-                "prober poll address for icmp replica is invalid: {} (error: {})",
-                host, err
+            // Notice: a closed target port results in a 'connection refused' error here.
+            debug!(
+                "prober poll udp error for target: {}:{} (error: {})",
+                host, port, err
             );
 
-            // Consider invalid URL as a failure
-            return (false, None);
+            (Status::Dead, None)
         }
+    }
+}
+
+fn proceed_replica_request_dns(
+    host: &str,
+    port: u16,
+    query: &DNSQuery,
+) -> (Status, Option<Duration>) {
+    debug!(
+        "prober poll will fire for dns target: {}:{} with name: {} and type: {}",
+        host, port, query.name, query.record_type
+    );
+
+    let (query_id, query_message) = match query.encode() {
+        Ok(query) => query,
+        Err(_) => return (Status::Dead, None),
     };
 
-    // If there was no early return, consider all the hosts as reachable for replica
-    (true, maximum_rtt)
+    // Send query over UDP first, then retry over TCP if the UDP response was truncated (ie. \
+    //   it was too large), or if no response was received over UDP (eg. the resolver only \
+    //   listens over TCP)
+    let response_message =
+        match proceed_replica_request_dns_udp(host, port, query_id, &query_message) {
+            Some(response_message) if !response_message.truncated() => response_message,
+            udp_response_message => {
+                debug!(
+                "prober poll got {} dns response over udp from target: {}:{}, retrying over tcp",
+                if udp_response_message.is_some() {
+                    "truncated"
+                } else {
+                    "no"
+                },
+                host,
+                port
+            );
+
+                match acquire_tcp_stream(host, port)
+                    .ok_or(())
+                    .and_then(|mut stream| dns_exchange(&mut stream, &query_message))
+                    .and_then(|response| dns_decode(query_id, &response))
+                {
+                    Ok(response_message) => response_message,
+                    Err(_) => return (Status::Dead, None),
+                }
+            }
+        };
+
+    (dns_evaluate(query, &response_message), None)
+}
+
+fn proceed_replica_request_dns_udp(
+    host: &str,
+    port: u16,
+    query_id: u16,
+    query_message: &[u8],
+) -> Option<DNSMessage> {
+    let socket = acquire_udp_socket(host, port)?;
+
+    if let Err(err) = socket.send(query_message) {
+        debug!(
+            "prober poll could not send dns query over udp to target: {}:{} (error: {})",
+            host, port, err
+        );
+
+        return None;
+    }
+
+    let deadline = Instant::now() + acquire_dead_timeout();
+    let mut response = [0; 65536];
+
+    // Notice: unrelated responses (eg. with a mismatched query identifier) are discarded, and \
+    //   reading goes on until the deadline, as they may be spoofed or late responses to a \
+    //   previous query (see RFC 5452).
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() || socket.set_read_timeout(Some(remaining)).is_err() {
+            debug!(
+                "prober poll got no related dns response over udp from target: {}:{}",
+                host, port
+            );
+
+            return None;
+        }
+
+        match socket.recv(&mut response) {
+            Ok(response_size) => {
+                if let Ok(response_message) = dns_decode(query_id, &response[..response_size]) {
+                    return Some(response_message);
+                }
+            }
+            Err(err) => {
+                debug!(
+                    "prober poll got no dns response over udp from target: {}:{} (error: {})",
+                    host, port, err
+                );
+
+                return None;
+            }
+        }
+    }
 }
 
-fn proceed_replica_request_tcp(host: &str, port: u16) -> (bool, Option<Duration>) {
+fn proceed_replica_request_unix(
+    node: &ConfigProbeServiceNode,
+    path: &str,
+) -> (Status, Option<Duration>) {
+    debug!("prober poll will fire for unix target: {}", path);
+
+    match acquire_unix_stream(path) {
+        Some(mut stream) => {
+            // Notice: unix sockets support the same payload / banner options as TCP replicas.
+            if tcp_has_exchange(node, None) {
+                match tcp_exchange(node, None, &mut stream) {
+                    Ok(banner) => (tcp_evaluate(node, None, &banner), None),
+                    Err(_) => (Status::Dead, None),
+                }
+            } else {
+                (Status::Healthy, None)
+            }
+        }
+        None => (Status::Dead, None),
+    }
+}
+
+fn proceed_replica_request_tls(
+    node: &ConfigProbeServiceNode,
+    host: &str,
+    port: u16,
+) -> (Status, Option<Duration>) {
+    debug!("prober poll will fire for tls target: {}:{}", host, port);
+
+    if let Some(stream) = acquire_tcp_stream(host, port) {
+        if let Ok((_, tls_status)) = tls_handshake(node, host, stream) {
+            return (tls_status, None);
+        }
+    }
+
+    (Status::Dead, None)
+}
+
+fn proceed_replica_request_grpc(
+    node: &ConfigProbeServiceNode,
+    host: &str,
+    port: u16,
+    service: &str,
+    is_secure: bool,
+) -> (Status, Option<Duration>) {
+    debug!("prober poll will fire for grpc target: {}:{}", host, port);
+
+    let authority = format!("{}:{}", host, port);
+
+    // Acquire health check response (over a secure stream if gRPCS)
+    let (response, tls_status): (Result<GRPCResponse, ()>, Status) =
+        match acquire_tcp_stream(host, port) {
+            Some(mut stream) => {
+                if is_secure {
+                    match tls_handshake_alpn(node, host, stream, GRPC_ALPN_PROTOCOL) {
+                        Ok((mut tls_stream, tls_status)) => (
+                            grpc_request(&authority, service, is_secure, &mut tls_stream),
+                            tls_status,
+                        ),
+                        Err(_) => (Err(()), Status::Dead),
+                    }
+                } else {
+                    (
+                        grpc_request(&authority, service, is_secure, &mut stream),
+                        Status::Healthy,
+                    )
+                }
+            }
+            None => (Err(()), Status::Dead),
+        };
+
+    match response {
+        Ok(response) => (grpc_evaluate(service, &response).worst(tls_status), None),
+        Err(_) => (Status::Dead, None),
+    }
+}
+
+fn proceed_replica_request_postgres(
+    node: &ConfigProbeServiceNode,
+    url: &str,
+) -> (Status, Option<Duration>) {
+    // Notice: never print the raw URL, as it contains the database password.
+    debug!(
+        "prober poll will fire for postgres target: {}",
+        ReplicaURL::redact(url)
+    );
+
+    (
+        database_query_postgres(node, url, acquire_dead_timeout()),
+        None,
+    )
+}
+
+fn proceed_replica_request_mysql(
+    node: &ConfigProbeServiceNode,
+    url: &str,
+) -> (Status, Option<Duration>) {
+    // Notice: never print the raw URL, as it contains the database password.
+    debug!(
+        "prober poll will fire for mysql target: {}",
+        ReplicaURL::redact(url)
+    );
+
+    (
+        database_query_mysql(node, url, acquire_dead_timeout()),
+        None,
+    )
+}
+
+fn proceed_replica_request_http(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+    url: &str,
+    options: &ResolveOptions,
+) -> (Status, Option<Duration>) {
+    // Notice: never print the raw URL, as it may contain secrets.
+    let url_redacted = ReplicaURL::redact(url);
+
+    debug!("prober poll will fire for http target: {}", &url_redacted);
+
+    // Acquire target host and port
+    let (host, port, is_secure) = match Url::parse(url) {
+        Ok(url_parsed) => match (url_parsed.host(), url_parsed.port_or_known_default()) {
+            (Some(host), Some(port)) => (
+                ReplicaURL::host_string(host),
+                port,
+                url_parsed.scheme() == "https",
+            ),
+            _ => return (Status::Dead, None),
+        },
+        Err(_) => return (Status::Dead, None),
+    };
+
+    let address_values = match resolve(&host, port, options) {
+        Ok(address_values) => address_values,
+        Err(_) => return (Status::Dead, None),
+    };
+
+    let result = resolve_probe(&address_values, options, |address_value| {
+        let mut request = HTTPRequest::new(node, url);
+        let mut target = (host.to_owned(), port, is_secure);
+        let mut address = *address_value;
+        let mut tls_status = Status::Healthy;
+
+        // Acquire replica response (following redirects, if enabled)
+        for redirects in 0..=HTTP_REDIRECT_MAXIMUM {
+            let response =
+                proceed_replica_request_http_once(node, &request, &target.0, target.2, &address);
+
+            let (response, response_tls_status) = match response {
+                Some(response) => response,
+                None => {
+                    debug!(
+                        "prober poll result was not received for url: {} from target: {}",
+                        &url_redacted, address
+                    );
+
+                    // Consider as DOWN.
+                    return (Status::Dead, None);
+                }
+            };
+
+            tls_status = tls_status.worst(response_tls_status);
+
+            debug!(
+                "prober poll result received for url: {} from target: {} with status: {}",
+                ReplicaURL::redact(&request.url),
+                address,
+                response.status_code
+            );
+
+            let location = match response.redirect_location() {
+                Some(location) if node.http_follow_redirects => location,
+                _ => {
+                    // Evaluate response status code and body (a TLS warning degrades the result)
+                    return (
+                        http_evaluate(node, response.status_code, &response.body).worst(tls_status),
+                        None,
+                    );
+                }
+            };
+
+            if redirects == HTTP_REDIRECT_MAXIMUM {
+                break;
+            }
+
+            // Prepare redirected request (a new connection is opened for each redirect)
+            request = match request.redirect(response.status_code, location) {
+                Ok(request) => request,
+                Err(_) => return (Status::Dead, None),
+            };
+
+            match proceed_replica_request_http_target(&request, options) {
+                Some((next_target, next_address)) => {
+                    // Notice: redirects to the same target keep probing the same address, so \
+                    //   that the resolution policy still applies to the whole redirect chain.
+                    if next_target != target {
+                        address = next_address;
+                    }
+
+                    target = next_target;
+                }
+                None => return (Status::Dead, None),
+            }
+        }
+
+        warn!(
+            "prober poll got too many http redirects for url: {} (maximum: {})",
+            &url_redacted, HTTP_REDIRECT_MAXIMUM
+        );
+
+        (Status::Dead, None)
+    });
+
+    record_failed_addresses(service_id, node, replica, &host, &result.failed);
+
+    (result.status, result.latency)
+}
+
+fn proceed_replica_request_http_once(
+    node: &ConfigProbeServiceNode,
+    request: &HTTPRequest,
+    host: &str,
+    is_secure: bool,
+    address: &SocketAddr,
+) -> Option<(HTTPResponse, Status)> {
+    // Acquire replica response (over a secure stream if HTTPS)
+    let mut stream = connect_tcp_stream(address)?;
+
+    if is_secure {
+        let (mut tls_stream, tls_status) = tls_handshake(node, host, stream).ok()?;
+
+        http_request(request, &mut tls_stream)
+            .ok()
+            .map(|response| (response, tls_status))
+    } else {
+        http_request(request, &mut stream)
+            .ok()
+            .map(|response| (response, Status::Healthy))
+    }
+}
+
+fn proceed_replica_request_http_target(
+    request: &HTTPRequest,
+    options: &ResolveOptions,
+) -> Option<((String, u16, bool), SocketAddr)> {
+    let url_parsed = Url::parse(&request.url).ok()?;
+
+    let host = ReplicaURL::host_string(url_parsed.host()?);
+    let port = url_parsed.port_or_known_default()?;
+
+    // Resolve redirect target (only its first address gets probed)
+    let address = resolve(&host, port, options).ok()?.into_iter().next()?;
+
+    Some(((host, port, url_parsed.scheme() == "https"), address))
+}
+
+fn proceed_replica_request_http_unix(
+    node: &ConfigProbeServiceNode,
+    path: &str,
+    url: &str,
+) -> (Status, Option<Duration>) {
+    debug!(
+        "prober poll will fire for http target: {} over unix socket: {}",
+        ReplicaURL::redact(url),
+        path
+    );
+
+    let response = match acquire_unix_stream(path) {
+        Some(mut stream) => http_request(&HTTPRequest::new(node, url), &mut stream),
+        None => Err(()),
+    };
+
+    // Evaluate response status code and body (same as HTTP replicas)
+    // Notice: redirects are not followed over unix sockets, as they point to network URLs.
+    match response {
+        Ok(response) => {
+            debug!(
+                "prober poll result received over unix socket: {} with status: {}",
+                path, response.status_code
+            );
+
+            (
+                http_evaluate(node, response.status_code, &response.body),
+                None,
+            )
+        }
+        Err(_) => {
+            debug!(
+                "prober poll result was not received over unix socket: {}",
+                path
+            );
+
+            (Status::Dead, None)
+        }
+    }
+}
+
+fn proceed_replica_request_websocket(
+    node: &ConfigProbeServiceNode,
+    url: &str,
+) -> (Status, Option<Duration>) {
+    // Notice: never print the raw URL, as it may contain secrets.
+    let url_redacted = ReplicaURL::redact(url);
+
+    debug!(
+        "prober poll will fire for websocket target: {}",
+        &url_redacted
+    );
+
+    // Acquire target host and port
+    let (host, port, is_secure) = match Url::parse(url) {
+        Ok(url_parsed) => match (url_parsed.host(), url_parsed.port_or_known_default()) {
+            (Some(host), Some(port)) => (
+                ReplicaURL::host_string(host),
+                port,
+                url_parsed.scheme() == "wss",
+            ),
+            _ => return (Status::Dead, None),
+        },
+        Err(_) => return (Status::Dead, None),
+    };
+
+    // Acquire upgrade response (over a secure stream if WSS, with the same settings as HTTPS)
+    let (response, tls_status): (Result<WebSocketResponse, ()>, Status) =
+        match acquire_tcp_stream(&host, port) {
+            Some(mut stream) => {
+                if is_secure {
+                    match tls_handshake(node, &host, stream) {
+                        Ok((mut tls_stream, tls_status)) => {
+                            (websocket_request(node, url, &mut tls_stream), tls_status)
+                        }
+                        Err(_) => (Err(()), Status::Dead),
+                    }
+                } else {
+                    (websocket_request(node, url, &mut stream), Status::Healthy)
+                }
+            }
+            None => (Err(()), Status::Dead),
+        };
+
+    match response {
+        Ok(response) => {
+            debug!(
+                "prober poll websocket result received for url: {} with status: {}",
+                &url_redacted, response.status_code
+            );
+
+            (websocket_evaluate(node, &response).worst(tls_status), None)
+        }
+        Err(_) => {
+            debug!(
+                "prober poll websocket result was not received for url: {}",
+                &url_redacted
+            );
+
+            (Status::Dead, None)
+        }
+    }
+}
+
+fn acquire_unix_stream(path: &str) -> Option<UnixStream> {
+    match UnixStream::connect(path) {
+        Ok(stream) => {
+            let dead_timeout = acquire_dead_timeout();
+
+            // Bound all further reads and writes to the dead timeout
+            if stream.set_read_timeout(Some(dead_timeout)).is_ok()
+                && stream.set_write_timeout(Some(dead_timeout)).is_ok()
+            {
+                return Some(stream);
+            }
+
+            None
+        }
+        Err(err) => {
+            debug!(
+                "prober poll could not connect to unix socket: {} (error: {})",
+                path, err
+            );
+
+            None
+        }
+    }
+}
+
+fn record_failed_addresses(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica: &ReplicaURL,
+    host: &str,
+    failed: &[IpAddr],
+) {
+    if !failed.is_empty() {
+        warn!(
+            "prober poll got failed addresses for host: {} on #{}:#{}:[{:?}] (failed: {:?})",
+            host, service_id, node.id, replica, failed
+        );
+    }
+
+    states_record_addresses(
+        service_id,
+        &node.id,
+        &ReportReplica::Poll(replica).as_state_id(),
+        failed,
+    );
+}
+
+fn acquire_tcp_stream(host: &str, port: u16) -> Option<TcpStream> {
     let address_results = (host, port).to_socket_addrs();
 
     if let Ok(mut address) = address_results {
         if let Some(address_value) = address.next() {
-            debug!("prober poll will fire for tcp target: {}", address_value);
-
-            return match TcpStream::connect_timeout(&address_value, acquire_dead_timeout()) {
-                Ok(_) => (true, None),
-                Err(_) => (false, None),
-            };
+            return connect_tcp_stream(&address_value);
         }
     }
 
-    (false, None)
+    None
 }
 
-fn proceed_replica_request_http(url: &str) -> (bool, Option<Duration>) {
-    debug!("prober poll will fire for http target: {}", &url);
+fn connect_tcp_stream(address_value: &SocketAddr) -> Option<TcpStream> {
+    debug!("prober poll will connect to tcp target: {}", address_value);
 
-    // Unpack dead timeout
     let dead_timeout = acquire_dead_timeout();
 
-    // Acquire replica response
-    let mut response_body = Vec::new();
+    if let Ok(stream) = TcpStream::connect_timeout(address_value, dead_timeout) {
+        // Bound all further reads and writes to the dead timeout
+        if stream.set_read_timeout(Some(dead_timeout)).is_ok()
+            && stream.set_write_timeout(Some(dead_timeout)).is_ok()
+        {
+            return Some(stream);
+        }
+    }
 
-    let response = Request::new(&Uri::try_from(url).expect("invalid replica request uri"))
-        .connect_timeout(Some(dead_timeout))
-        .read_timeout(Some(dead_timeout))
-        .write_timeout(Some(dead_timeout))
-        .method(Method::HEAD)
-        .header("User-Agent", &*POLL_HTTP_HEADER_USERAGENT)
-        .send(&mut response_body);
+    None
+}
 
-    // Handle response
-    if let Ok(response) = response {
-        let status_code = u16::from(response.status_code());
+fn acquire_udp_socket(host: &str, port: u16) -> Option<UdpSocket> {
+    let address_results = (host, port).to_socket_addrs();
 
-        debug!(
-            "prober poll result received for url: {} with status: {}",
-            &url, status_code
-        );
+    if let Ok(mut address) = address_results {
+        if let Some(address_value) = address.next() {
+            debug!("prober poll will connect to udp target: {}", address_value);
+
+            // Bind local socket on the same address family as the target
+            let bind_address: SocketAddr = if address_value.is_ipv4() {
+                (Ipv4Addr::UNSPECIFIED, 0).into()
+            } else {
+                (Ipv6Addr::UNSPECIFIED, 0).into()
+            };
 
-        // Consider as UP?
-        if status_code >= HTTP_STATUS_HEALTHY_ABOVE && status_code < HTTP_STATUS_HEALTHY_BELOW {
-            return (true, None);
+            if let Ok(socket) = UdpSocket::bind(bind_address) {
+                // Notice: connecting an UDP socket filters received datagrams on the target \
+                //   address, and lets us receive errors for closed target ports.
+                if socket.connect(address_value).is_ok()
+                    && socket
+                        .set_read_timeout(Some(acquire_dead_timeout()))
+                        .is_ok()
+                    && socket
+                        .set_write_timeout(Some(acquire_dead_timeout()))
+                        .is_ok()
+                {
+                    return Some(socket);
+                }
+            }
         }
-    } else {
-        debug!("prober poll result was not received for url: {}", &url);
     }
 
-    // Consider as DOWN.
-    (false, None)
+    None
 }
 
 fn acquire_dead_timeout() -> Duration {
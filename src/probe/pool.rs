@@ -0,0 +1,73 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::THREAD_NAME_PROBE_WORKER;
+
+type PoolJob = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct Pool {
+    sender: Sender<PoolJob>,
+}
+
+impl Pool {
+    pub fn new(size: u16) -> Pool {
+        let (sender, receiver) = mpsc::channel::<PoolJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        // Notice: always spawn at least one worker, as a zero-sized pool would never process \
+        //   any job that gets submitted to it.
+        let size = size.max(1);
+
+        debug!("spawning probe worker pool with {} workers", size);
+
+        for index in 0..size {
+            let receiver = receiver.clone();
+
+            thread::Builder::new()
+                .name(format!("{}-{}", THREAD_NAME_PROBE_WORKER, index))
+                .spawn(move || Self::work(receiver))
+                .expect("could not spawn probe worker thread");
+        }
+
+        Pool { sender }
+    }
+
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // Notice: sending can only fail if all workers are gone, which is a fatal condition.
+        self.sender
+            .send(Box::new(job))
+            .expect("probe worker pool is gone");
+    }
+
+    fn work(receiver: Arc<Mutex<Receiver<PoolJob>>>) {
+        loop {
+            // Acquire next job (release lock as soon as a job is received)
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => break,
+            };
+
+            // Notice: a panicking job must not take its worker down with it, as the pool would \
+            //   otherwise shrink until no worker is left to process jobs.
+            match job {
+                Ok(job) => {
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("probe worker job panicked, recovering worker");
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    }
+}
@@ -10,35 +10,196 @@ use serde::de::{Error, Visitor};
 use serde::{Deserialize, Deserializer};
 use url::{Host, Url};
 
-#[derive(Serialize, Debug, Clone)]
+use super::dns::{DNSQuery, DNS_PORT_DEFAULT};
+use super::resolve::{ResolveOptions, ResolvePolicy};
+use super::tcp::TCPProtocol;
+
+const REDACTED_SECRET: &str = "redacted";
+const REDACTED_QUERY_KEYS: &[&str] = &["token", "key", "secret", "password", "auth"];
+
+const UNIX_HTTP_HOST: &str = "localhost";
+
+#[derive(Serialize, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ReplicaURL {
-    ICMP(String, String),
-    TCP(String, String, u16),
-    HTTP(String, String),
-    HTTPS(String, String),
+    ICMP(String, String, ResolveOptions),
+    TCP(String, String, u16, Option<TCPProtocol>, ResolveOptions),
+    TLS(String, String, u16),
+    UDP(String, String, u16),
+    DNS(String, String, u16, DNSQuery),
+    GRPC(String, String, u16, String),
+    GRPCS(String, String, u16, String),
+    Postgres(String, String),
+    MySQL(String, String),
+    Unix(String, String),
+    HTTP(String, String, ResolveOptions),
+    HTTPS(String, String, ResolveOptions),
+    HTTPUnix(String, String, String),
+    WS(String, String),
+    WSS(String, String),
 }
 
 impl ReplicaURL {
     pub fn parse_from(raw_url: &str) -> Result<ReplicaURL, ()> {
         match Url::parse(raw_url) {
             Ok(url) => match url.scheme() {
-                "icmp" => match url.host() {
-                    Some(host) => Ok(ReplicaURL::ICMP(
+                // Notice: ICMP replicas check all resolved addresses by default, while TCP and \
+                //   HTTP replicas only check the first one (unless another policy is set).
+                "icmp" => match (
+                    url.host(),
+                    ResolveOptions::parse_from(&url, ResolvePolicy::All),
+                ) {
+                    (Some(host), Ok(options)) => Ok(ReplicaURL::ICMP(
+                        raw_url.to_owned(),
+                        Self::host_string(host),
+                        options,
+                    )),
+                    _ => Err(()),
+                },
+                "tcp" => match (
+                    url.host(),
+                    url.port(),
+                    ResolveOptions::parse_from(&url, ResolvePolicy::First),
+                ) {
+                    (Some(host), Some(port), Ok(options)) => Ok(ReplicaURL::TCP(
+                        raw_url.to_owned(),
+                        Self::host_string(host),
+                        port,
+                        None,
+                        options,
+                    )),
+                    _ => Err(()),
+                },
+                "smtp" | "ssh" | "ftp" | "redis" | "imap" | "pop3" => match (
+                    url.host(),
+                    TCPProtocol::from_scheme(url.scheme()),
+                    ResolveOptions::parse_from(&url, ResolvePolicy::First),
+                ) {
+                    (Some(host), Some(protocol), Ok(options)) => Ok(ReplicaURL::TCP(
+                        raw_url.to_owned(),
+                        Self::host_string(host),
+                        url.port().unwrap_or(protocol.default_port()),
+                        Some(protocol),
+                        options,
+                    )),
+                    _ => Err(()),
+                },
+                "tls" => match (url.host(), url.port()) {
+                    (Some(host), Some(port)) => Ok(ReplicaURL::TLS(
                         raw_url.to_owned(),
                         Self::host_string(host),
+                        port,
                     )),
                     _ => Err(()),
                 },
-                "tcp" => match (url.host(), url.port()) {
-                    (Some(host), Some(port)) => Ok(ReplicaURL::TCP(
+                "udp" => match (url.host(), url.port()) {
+                    (Some(host), Some(port)) => Ok(ReplicaURL::UDP(
                         raw_url.to_owned(),
                         Self::host_string(host),
                         port,
                     )),
                     _ => Err(()),
                 },
-                "http" => Ok(ReplicaURL::HTTP(raw_url.to_owned(), url.to_string())),
-                "https" => Ok(ReplicaURL::HTTPS(raw_url.to_owned(), url.to_string())),
+                "dns" => match (url.host(), DNSQuery::parse_from(&url)) {
+                    (Some(host), Ok(query)) => Ok(ReplicaURL::DNS(
+                        raw_url.to_owned(),
+                        Self::host_string(host),
+                        url.port().unwrap_or(DNS_PORT_DEFAULT),
+                        query,
+                    )),
+                    _ => Err(()),
+                },
+                "grpc" | "grpcs" => match (url.host(), url.port()) {
+                    (Some(host), Some(port)) => {
+                        let service = url.path().trim_start_matches('/').to_owned();
+
+                        if url.scheme() == "grpcs" {
+                            Ok(ReplicaURL::GRPCS(
+                                raw_url.to_owned(),
+                                Self::host_string(host),
+                                port,
+                                service,
+                            ))
+                        } else {
+                            Ok(ReplicaURL::GRPC(
+                                raw_url.to_owned(),
+                                Self::host_string(host),
+                                port,
+                                service,
+                            ))
+                        }
+                    }
+                    _ => Err(()),
+                },
+                // Notice: database URLs hold the database password, which must never be \
+                //   reported to Vigil, therefore the raw URL is stored redacted.
+                "postgres" | "postgresql" => match url.host() {
+                    Some(_) => Ok(ReplicaURL::Postgres(
+                        Self::redact(raw_url),
+                        raw_url.to_owned(),
+                    )),
+                    _ => Err(()),
+                },
+                "mysql" => match url.host() {
+                    Some(_) => Ok(ReplicaURL::MySQL(Self::redact(raw_url), raw_url.to_owned())),
+                    _ => Err(()),
+                },
+                "unix" => match url.path() {
+                    "" | "/" => Err(()),
+                    path => Ok(ReplicaURL::Unix(raw_url.to_owned(), path.to_owned())),
+                },
+                "http+unix" => {
+                    // Split socket path and request path (eg. '/run/app.sock:/health')
+                    let (socket_path, request_path) = match url.path().split_once(':') {
+                        Some((socket_path, request_path)) => (socket_path, request_path),
+                        None => (url.path(), "/"),
+                    };
+
+                    if socket_path.is_empty() || !request_path.starts_with('/') {
+                        return Err(());
+                    }
+
+                    // Notice: the request URL is only used to build the request message, \
+                    //   therefore its host is a placeholder.
+                    let mut request_url = format!("http://{}{}", UNIX_HTTP_HOST, request_path);
+
+                    if let Some(query) = url.query() {
+                        request_url.push('?');
+                        request_url.push_str(query);
+                    }
+
+                    Ok(ReplicaURL::HTTPUnix(
+                        raw_url.to_owned(),
+                        socket_path.to_owned(),
+                        request_url,
+                    ))
+                }
+                "http" | "https" => {
+                    match ResolveOptions::parse_namespaced_from(&url, ResolvePolicy::First) {
+                        Ok(options) => {
+                            let mut url = url;
+
+                            ResolveOptions::strip_from(&mut url);
+
+                            if url.scheme() == "https" {
+                                Ok(ReplicaURL::HTTPS(
+                                    raw_url.to_owned(),
+                                    url.to_string(),
+                                    options,
+                                ))
+                            } else {
+                                Ok(ReplicaURL::HTTP(
+                                    raw_url.to_owned(),
+                                    url.to_string(),
+                                    options,
+                                ))
+                            }
+                        }
+                        _ => Err(()),
+                    }
+                }
+                "ws" => Ok(ReplicaURL::WS(raw_url.to_owned(), url.to_string())),
+                "wss" => Ok(ReplicaURL::WSS(raw_url.to_owned(), url.to_string())),
                 _ => Err(()),
             },
             _ => Err(()),
@@ -47,14 +208,60 @@ impl ReplicaURL {
 
     pub fn get_raw(&self) -> &str {
         match self {
-            &ReplicaURL::ICMP(ref raw_url, _) => raw_url,
-            &ReplicaURL::TCP(ref raw_url, _, _) => raw_url,
-            &ReplicaURL::HTTP(ref raw_url, _) => raw_url,
-            &ReplicaURL::HTTPS(ref raw_url, _) => raw_url,
+            ReplicaURL::ICMP(raw_url, _, _) => raw_url,
+            ReplicaURL::TCP(raw_url, _, _, _, _) => raw_url,
+            ReplicaURL::TLS(raw_url, _, _) => raw_url,
+            ReplicaURL::UDP(raw_url, _, _) => raw_url,
+            ReplicaURL::DNS(raw_url, _, _, _) => raw_url,
+            ReplicaURL::GRPC(raw_url, _, _, _) => raw_url,
+            ReplicaURL::GRPCS(raw_url, _, _, _) => raw_url,
+            ReplicaURL::Postgres(raw_url, _) => raw_url,
+            ReplicaURL::MySQL(raw_url, _) => raw_url,
+            ReplicaURL::Unix(raw_url, _) => raw_url,
+            ReplicaURL::HTTP(raw_url, _, _) => raw_url,
+            ReplicaURL::HTTPS(raw_url, _, _) => raw_url,
+            ReplicaURL::HTTPUnix(raw_url, _, _) => raw_url,
+            ReplicaURL::WS(raw_url, _) => raw_url,
+            ReplicaURL::WSS(raw_url, _) => raw_url,
+        }
+    }
+
+    pub fn redact(raw_url: &str) -> String {
+        // Redact secrets from an URL, so that it can be safely printed in logs (ie. the userinfo \
+        //   password and any query parameter that looks like it holds a secret value)
+        match Url::parse(raw_url) {
+            Ok(mut url) => {
+                if url.password().is_some() {
+                    url.set_password(Some(REDACTED_SECRET)).ok();
+                }
+
+                if url.query().is_some() {
+                    let query_pairs: Vec<(String, String)> = url
+                        .query_pairs()
+                        .map(|(key, value)| {
+                            let key_lower = key.to_lowercase();
+
+                            if REDACTED_QUERY_KEYS
+                                .iter()
+                                .any(|secret_key| key_lower.contains(secret_key))
+                            {
+                                (key.into_owned(), REDACTED_SECRET.to_string())
+                            } else {
+                                (key.into_owned(), value.into_owned())
+                            }
+                        })
+                        .collect();
+
+                    url.query_pairs_mut().clear().extend_pairs(query_pairs);
+                }
+
+                url.to_string()
+            }
+            Err(_) => raw_url.to_owned(),
         }
     }
 
-    fn host_string(host: Host<&str>) -> String {
+    pub fn host_string(host: Host<&str>) -> String {
         // Convert internal host value into string. This is especially useful for IPv6 addresses, \
         //   which we need returned in '::1' format; as they would otherwise be returned in \
         //   '[::1]' format using built-in top-level 'to_string()' method on the 'Host' trait. The \
@@ -68,6 +275,69 @@ impl ReplicaURL {
     }
 }
 
+impl fmt::Debug for ReplicaURL {
+    fn fmt(&self, format: &mut fmt::Formatter) -> fmt::Result {
+        // Notice: replica URLs are printed in logs, therefore they must never leak secrets.
+        match self {
+            ReplicaURL::ICMP(_, host, _) => format.debug_tuple("ICMP").field(host).finish(),
+            ReplicaURL::TCP(_, host, port, None, _) => {
+                format.debug_tuple("TCP").field(host).field(port).finish()
+            }
+            ReplicaURL::TCP(_, host, port, Some(protocol), _) => format
+                .debug_tuple("TCP")
+                .field(host)
+                .field(port)
+                .field(protocol)
+                .finish(),
+            ReplicaURL::TLS(_, host, port) => {
+                format.debug_tuple("TLS").field(host).field(port).finish()
+            }
+            ReplicaURL::UDP(_, host, port) => {
+                format.debug_tuple("UDP").field(host).field(port).finish()
+            }
+            ReplicaURL::DNS(_, host, port, query) => format
+                .debug_tuple("DNS")
+                .field(host)
+                .field(port)
+                .field(&query.name)
+                .field(&query.record_type)
+                .finish(),
+            ReplicaURL::GRPC(_, host, port, service) => format
+                .debug_tuple("GRPC")
+                .field(host)
+                .field(port)
+                .field(service)
+                .finish(),
+            ReplicaURL::GRPCS(_, host, port, service) => format
+                .debug_tuple("GRPCS")
+                .field(host)
+                .field(port)
+                .field(service)
+                .finish(),
+            ReplicaURL::Postgres(raw_url, _) => {
+                format.debug_tuple("Postgres").field(raw_url).finish()
+            }
+            ReplicaURL::MySQL(raw_url, _) => format.debug_tuple("MySQL").field(raw_url).finish(),
+            ReplicaURL::Unix(_, path) => format.debug_tuple("Unix").field(path).finish(),
+            ReplicaURL::HTTP(_, url, _) => format
+                .debug_tuple("HTTP")
+                .field(&Self::redact(url))
+                .finish(),
+            ReplicaURL::HTTPS(_, url, _) => format
+                .debug_tuple("HTTPS")
+                .field(&Self::redact(url))
+                .finish(),
+            ReplicaURL::HTTPUnix(_, path, url) => format
+                .debug_tuple("HTTPUnix")
+                .field(path)
+                .field(&Self::redact(url))
+                .finish(),
+            ReplicaURL::WS(_, url) => format.debug_tuple("WS").field(&Self::redact(url)).finish(),
+            ReplicaURL::WSS(_, url) => format.debug_tuple("WSS").field(&Self::redact(url)).finish(),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for ReplicaURL {
     fn deserialize<D>(de: D) -> Result<ReplicaURL, D::Error>
     where
@@ -79,7 +349,9 @@ impl<'de> Deserialize<'de> for ReplicaURL {
             type Value = ReplicaURL;
 
             fn expecting(&self, format: &mut fmt::Formatter) -> fmt::Result {
-                format.write_str("an ICMP, TCP, HTTP or HTTPS url")
+                format.write_str(
+                    "an ICMP, TCP, TLS, UDP, DNS, gRPC, PostgreSQL, MySQL, Unix, HTTP, HTTPS or WebSocket url (or a TCP protocol url)",
+                )
             }
 
             fn visit_str<E: Error>(self, value: &str) -> Result<ReplicaURL, E> {
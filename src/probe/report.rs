@@ -18,7 +18,14 @@ use std::thread;
 use std::time::Duration;
 
 use super::replica::ReplicaURL;
+use super::spool::{
+    append as spool_append, replay as spool_replay, supersede as spool_supersede, SpoolEntry,
+};
+use super::states::{
+    count_report as states_count_report, count_report_target as states_count_report_target,
+};
 use super::status::Status;
+use crate::config::config::ConfigReport;
 use crate::config::config::{ConfigProbeService, ConfigProbeServiceNode};
 use crate::APP_CONF;
 
@@ -27,6 +34,17 @@ pub const REPORT_HTTP_CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 const RETRY_STATUS_TIMES: u8 = 4;
 const RETRY_STATUS_AFTER_SECONDS: u64 = 2;
 
+const REPORT_GROUP_FAILOVER: &str = "failover";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ReportTargetMode {
+    #[serde(rename = "failover")]
+    Failover,
+
+    #[serde(rename = "broadcast")]
+    Broadcast,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ReportReplica<'a> {
     Poll(&'a ReplicaURL),
@@ -40,13 +58,21 @@ struct ReportPayload<'a> {
     interval: u64,
 }
 
+struct ReportTarget {
+    key: String,
+    endpoint: &'static str,
+    authorization: String,
+}
+
+struct ReportGroup {
+    key: String,
+    targets: Vec<ReportTarget>,
+}
+
 lazy_static! {
     pub static ref REPORT_HTTP_HEADER_USERAGENT: String =
         format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    pub static ref REPORT_HTTP_HEADER_AUTHORIZATION: String = format!(
-        "Basic {}",
-        base64_encoder.encode(&format!(":{}", APP_CONF.report.token))
-    );
+    static ref REPORT_GROUPS: Vec<ReportGroup> = make_groups(&APP_CONF.report);
 }
 
 impl<'a> ReportReplica<'a> {
@@ -56,10 +82,28 @@ impl<'a> ReportReplica<'a> {
             Self::Script(replica) => replica,
         }
     }
+
+    pub fn as_state_id(&self) -> String {
+        // Notice: never expose raw replica URLs in local states, as they may contain secrets.
+        match self {
+            Self::Poll(replica) => ReplicaURL::redact(replica.get_raw()),
+            Self::Script(replica) => replica.to_string(),
+        }
+    }
 }
 
-pub fn generate_url(path: &str) -> String {
-    format!("{}/{}", &APP_CONF.report.endpoint, path)
+impl ReportTarget {
+    fn new(endpoint: &'static str, token: &str) -> ReportTarget {
+        ReportTarget {
+            key: ReplicaURL::redact(endpoint),
+            endpoint,
+            authorization: format!("Basic {}", base64_encoder.encode(format!(":{}", token))),
+        }
+    }
+}
+
+pub fn generate_url(endpoint: &str, path: &str) -> String {
+    format!("{}/{}", endpoint, path)
 }
 
 pub fn status<'a>(
@@ -69,24 +113,153 @@ pub fn status<'a>(
     status: &Status,
     interval: u64,
 ) -> Result<(), ()> {
-    // Attempt to acquire (first attempt)
-    status_attempt(service, node, replica, status, interval, 0)
+    let payload = ReportPayload {
+        replica: replica.as_str(),
+        interval,
+        health: status.as_str(),
+    };
+
+    // Deliver to each report group independently (ie. the failover chain, and each broadcast \
+    //   target), so that an unreachable target does not prevent delivery to other targets.
+    // Notice: the report is considered as failed only if the failover chain failed, as \
+    //   broadcast targets are secondary (eg. a staging status page), unless there are only \
+    //   broadcast targets, in which case a single target accepting the report is enough.
+    let (mut primary_result, mut has_success) = (None, false);
+
+    for group in REPORT_GROUPS.iter() {
+        // Replay spooled reports first (if any), so that they get delivered before this report
+        spool_replay(&group.key, |entry| {
+            status_request_group(
+                group,
+                &entry.service,
+                &entry.node,
+                None,
+                &ReportPayload {
+                    replica: &entry.replica,
+                    health: &entry.health,
+                    interval: entry.interval,
+                },
+            )
+        });
+
+        // Prevent any spooled report for this replica that is still being replayed (by another \
+        //   report) from being delivered after this newer report
+        spool_supersede(&group.key, &service.id, &node.id, payload.replica);
+
+        // Attempt to acquire (first attempt)
+        let result = status_attempt(group, service, node, replica, &payload, 0);
+
+        if group.is_primary() {
+            primary_result = Some(result);
+        }
+
+        if result.is_ok() {
+            has_success = true;
+        } else {
+            error!(
+                "failed reporting to report group: {} on #{}:#{}:[{:?}]",
+                group.key, service.id, node.id, replica
+            );
+
+            // Spool failed report, so that it can be replayed later (if spool is enabled)
+            spool_append(SpoolEntry::new(
+                &group.key,
+                &service.id,
+                &node.id,
+                payload.replica,
+                payload.health,
+                interval,
+            ));
+        }
+    }
+
+    match primary_result {
+        Some(result) => result,
+        None if has_success => Ok(()),
+        None => Err(()),
+    }
+}
+
+pub fn validate(report: &ConfigReport) -> Result<(), String> {
+    match (&report.endpoint, &report.token) {
+        (Some(_), Some(_)) | (None, None) => {}
+        _ => return Err("both a report endpoint and token must be set".to_string()),
+    }
+
+    if report.endpoint.is_none() && report.target.is_empty() {
+        return Err("at least one report endpoint or target must be set".to_string());
+    }
+
+    Ok(())
+}
+
+impl ReportGroup {
+    fn is_primary(&self) -> bool {
+        self.key == REPORT_GROUP_FAILOVER
+    }
+}
+
+fn make_groups(report: &'static ConfigReport) -> Vec<ReportGroup> {
+    // Notice: the legacy report endpoint is used as the first failover target, if any.
+    let mut failover_targets = Vec::new();
+    let mut groups = Vec::new();
+
+    if let (Some(endpoint), Some(token)) = (&report.endpoint, &report.token) {
+        failover_targets.push(ReportTarget::new(endpoint, token));
+    }
+
+    for target in &report.target {
+        match target.mode {
+            ReportTargetMode::Failover => {
+                failover_targets.push(ReportTarget::new(&target.endpoint, &target.token))
+            }
+            ReportTargetMode::Broadcast => {
+                let target = ReportTarget::new(&target.endpoint, &target.token);
+
+                groups.push(ReportGroup {
+                    key: target.key.to_owned(),
+                    targets: vec![target],
+                })
+            }
+        }
+    }
+
+    if !failover_targets.is_empty() {
+        groups.insert(
+            0,
+            ReportGroup {
+                key: REPORT_GROUP_FAILOVER.to_string(),
+                targets: failover_targets,
+            },
+        );
+    }
+
+    groups
 }
 
 fn status_attempt<'a>(
+    group: &ReportGroup,
     service: &ConfigProbeService,
     node: &ConfigProbeServiceNode,
     replica: ReportReplica<'a>,
-    status: &Status,
-    interval: u64,
+    payload: &ReportPayload,
     attempt: u8,
 ) -> Result<(), ()> {
     info!(
-        "running status report attempt #{} on #{}:#{}:[{:?}]",
-        attempt, service.id, node.id, replica
+        "running status report attempt #{} to group: {} on #{}:#{}:[{:?}]",
+        attempt, group.key, service.id, node.id, replica
     );
 
-    match status_request(service, node, replica, status, interval) {
+    let replica_id = replica.as_state_id();
+    let result = status_request_group(group, &service.id, &node.id, Some(&replica_id), payload);
+
+    // Count report for the replica (only for the failover chain, as its result is the one that \
+    //   gets reported for the replica; broadcast targets are counted per-target)
+    if group.is_primary() {
+        states_count_report(&service.id, &node.id, &replica_id, result.is_ok());
+    }
+
+    match result {
         Ok(_) => Ok(()),
         Err(_) => {
             let next_attempt = attempt + 1;
@@ -95,41 +268,73 @@ fn status_attempt<'a>(
                 Err(())
             } else {
                 error!(
-                    "status report attempt #{} failed on #{}:#{}:[{:?}], will retry",
-                    attempt, service.id, node.id, replica
+                    "status report attempt #{} to group: {} failed on #{}:#{}:[{:?}], will retry",
+                    attempt, group.key, service.id, node.id, replica
                 );
 
                 // Retry after delay
                 thread::sleep(Duration::from_secs(RETRY_STATUS_AFTER_SECONDS));
 
-                status_attempt(service, node, replica, status, interval, next_attempt)
+                status_attempt(group, service, node, replica, payload, next_attempt)
             }
         }
     }
 }
 
-fn status_request<'a>(
-    service: &ConfigProbeService,
-    node: &ConfigProbeServiceNode,
-    replica: ReportReplica<'a>,
-    status: &Status,
-    interval: u64,
+fn status_request_group(
+    group: &ReportGroup,
+    service_id: &str,
+    node_id: &str,
+    replica_id: Option<&str>,
+    payload: &ReportPayload,
+) -> Result<(), ()> {
+    // Deliver to the first target that accepts the report (in order)
+    for (index, target) in group.targets.iter().enumerate() {
+        let result = status_request(target, service_id, node_id, payload);
+
+        // Count report for this target (replayed reports are not counted)
+        if let Some(replica_id) = replica_id {
+            states_count_report_target(
+                service_id,
+                node_id,
+                replica_id,
+                &target.key,
+                result.is_ok(),
+            );
+        }
+
+        if result.is_ok() {
+            return Ok(());
+        }
+
+        if index + 1 < group.targets.len() {
+            warn!(
+                "report target: {} failed in group: {}, failing over to next target",
+                target.key, group.key
+            );
+        }
+    }
+
+    Err(())
+}
+
+fn status_request(
+    target: &ReportTarget,
+    service_id: &str,
+    node_id: &str,
+    payload: &ReportPayload,
 ) -> Result<(), ()> {
     // Generate report URL
-    let report_url = generate_url(&format!("reporter/{}/{}/", &service.id, &node.id));
+    let report_url = generate_url(
+        target.endpoint,
+        &format!("reporter/{}/{}/", service_id, node_id),
+    );
 
     debug!("generated report url: {}", &report_url);
 
-    // Generate report payload
-    let payload = ReportPayload {
-        replica: replica.as_str(),
-        interval: interval,
-        health: status.as_str(),
-    };
-
     // Encode payload to string
     // Notice: fail hard if payload is invalid (it should never be)
-    let payload_json = serde_json::to_vec(&payload).expect("invalid status request payload");
+    let payload_json = serde_json::to_vec(payload).expect("invalid status request payload");
 
     // Generate request URI
     let request_uri = Uri::try_from(report_url.as_str()).expect("invalid status request uri");
@@ -143,7 +348,7 @@ fn status_request<'a>(
         .write_timeout(Some(REPORT_HTTP_CLIENT_TIMEOUT))
         .method(Method::POST)
         .header("User-Agent", &*REPORT_HTTP_HEADER_USERAGENT)
-        .header("Authorization", &*REPORT_HTTP_HEADER_AUTHORIZATION)
+        .header("Authorization", &target.authorization)
         .header("Content-Type", "application/json")
         .header("Content-Length", &payload_json.len())
         .body(&payload_json)
@@ -0,0 +1,303 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use url::{form_urlencoded, Url};
+
+use super::status::Status;
+
+const RESOLVE_QUERY_KEY_FAMILY: &str = "vigil_family";
+const RESOLVE_QUERY_KEY_POLICY: &str = "vigil_policy";
+const RESOLVE_QUERY_KEY_FAMILY_PLAIN: &str = "family";
+const RESOLVE_QUERY_KEY_POLICY_PLAIN: &str = "policy";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ResolveFamily {
+    V4,
+    V6,
+    Any,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ResolvePolicy {
+    All,
+    Any,
+    First,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct ResolveOptions {
+    pub family: ResolveFamily,
+    pub policy: ResolvePolicy,
+}
+
+pub struct ResolveResult {
+    pub status: Status,
+    pub latency: Option<Duration>,
+    pub failed: Vec<IpAddr>,
+}
+
+impl ResolveFamily {
+    fn from_str(value: &str) -> Option<ResolveFamily> {
+        match value {
+            "v4" => Some(Self::V4),
+            "v6" => Some(Self::V6),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, address: &SocketAddr) -> bool {
+        match self {
+            Self::V4 => address.is_ipv4(),
+            Self::V6 => address.is_ipv6(),
+            Self::Any => true,
+        }
+    }
+}
+
+impl ResolvePolicy {
+    fn from_str(value: &str) -> Option<ResolvePolicy> {
+        match value {
+            "all" => Some(Self::All),
+            "any" => Some(Self::Any),
+            "first" => Some(Self::First),
+            _ => None,
+        }
+    }
+}
+
+impl ResolveOptions {
+    pub fn parse_from(url: &Url, default_policy: ResolvePolicy) -> Result<ResolveOptions, ()> {
+        // Notice: the query of non-HTTP replicas is not sent to the target, thus plain keys \
+        //   are accepted there (alongside namespaced keys).
+        Self::parse_keys_from(url, default_policy, true)
+    }
+
+    pub fn parse_namespaced_from(
+        url: &Url,
+        default_policy: ResolvePolicy,
+    ) -> Result<ResolveOptions, ()> {
+        Self::parse_keys_from(url, default_policy, false)
+    }
+
+    fn parse_keys_from(
+        url: &Url,
+        default_policy: ResolvePolicy,
+        allow_plain_keys: bool,
+    ) -> Result<ResolveOptions, ()> {
+        let mut options = ResolveOptions {
+            family: ResolveFamily::Any,
+            policy: default_policy,
+        };
+
+        // Notice: other query keys are left alone, as they may be meant for the target (eg. \
+        //   HTTP replicas), which is why resolve option keys are namespaced; invalid values for \
+        //   resolve option keys are errors though.
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                RESOLVE_QUERY_KEY_FAMILY => {
+                    options.family = ResolveFamily::from_str(&value).ok_or(())?;
+                }
+                RESOLVE_QUERY_KEY_POLICY => {
+                    options.policy = ResolvePolicy::from_str(&value).ok_or(())?;
+                }
+                RESOLVE_QUERY_KEY_FAMILY_PLAIN if allow_plain_keys => {
+                    options.family = ResolveFamily::from_str(&value).ok_or(())?;
+                }
+                RESOLVE_QUERY_KEY_POLICY_PLAIN if allow_plain_keys => {
+                    options.policy = ResolvePolicy::from_str(&value).ok_or(())?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+
+    pub fn strip_from(url: &mut Url) {
+        // Remove resolve options from the URL (so that they do not get sent to the target)
+        // Notice: other query parameters are kept as-is (ie. they are not re-encoded), and the \
+        //   query is not rewritten at all if it holds no resolve option.
+        let query = match url.query() {
+            Some(query) => query,
+            None => return,
+        };
+
+        let pairs: Vec<&str> = query.split('&').collect();
+
+        let kept_pairs: Vec<&str> = pairs
+            .iter()
+            .copied()
+            .filter(|pair| !Self::is_option_pair(pair))
+            .collect();
+
+        if kept_pairs.len() < pairs.len() {
+            let query = kept_pairs.join("&");
+
+            url.set_query(if query.is_empty() { None } else { Some(&query) });
+        }
+    }
+
+    fn is_option_pair(pair: &str) -> bool {
+        form_urlencoded::parse(pair.as_bytes())
+            .next()
+            .map(|(key, _)| key == RESOLVE_QUERY_KEY_FAMILY || key == RESOLVE_QUERY_KEY_POLICY)
+            .unwrap_or(false)
+    }
+}
+
+pub fn resolve(host: &str, port: u16, options: &ResolveOptions) -> Result<Vec<SocketAddr>, ()> {
+    let mut addresses: Vec<SocketAddr> = match (host, port).to_socket_addrs() {
+        Ok(addresses) => addresses
+            .filter(|address| options.family.matches(address))
+            .collect(),
+        Err(err) => {
+            error!(
+                "prober poll could not resolve host: {} (error: {})",
+                host, err
+            );
+
+            return Err(());
+        }
+    };
+
+    if addresses.is_empty() {
+        debug!(
+            "prober poll did not resolve any {:?} address for host: {}",
+            options.family, host
+        );
+
+        return Err(());
+    }
+
+    if options.policy == ResolvePolicy::First {
+        addresses.truncate(1);
+    }
+
+    Ok(addresses)
+}
+
+pub fn probe<F>(addresses: &[SocketAddr], options: &ResolveOptions, mut probe: F) -> ResolveResult
+where
+    F: FnMut(&SocketAddr) -> (Status, Option<Duration>),
+{
+    let mut results: Vec<(Status, Duration)> = Vec::new();
+    let mut failed = Vec::new();
+
+    // Probe all addresses (sequentially)
+    for address in addresses {
+        let start_time = Instant::now();
+        let (status, latency) = probe(address);
+
+        if status == Status::Dead {
+            failed.push(address.ip());
+        }
+
+        results.push((status, latency.unwrap_or_else(|| start_time.elapsed())));
+    }
+
+    // Notice: with the 'all' policy, the replica is as bad as its worst address (and as slow as \
+    //   its slowest responding one), while with the 'any' policy it is as good as its best \
+    //   address (and as fast as the fastest one with this status).
+    let status = match options.policy {
+        ResolvePolicy::Any => results
+            .iter()
+            .map(|(status, _)| *status)
+            .min_by_key(|status| status.as_value()),
+        ResolvePolicy::All | ResolvePolicy::First => results
+            .iter()
+            .map(|(status, _)| *status)
+            .max_by_key(|status| status.as_value()),
+    }
+    .unwrap_or(Status::Dead);
+
+    let latency = match options.policy {
+        ResolvePolicy::Any => results
+            .iter()
+            .filter(|(result_status, _)| *result_status == status)
+            .map(|(_, latency)| *latency)
+            .min(),
+        ResolvePolicy::All | ResolvePolicy::First => results
+            .iter()
+            .filter(|(result_status, _)| *result_status != Status::Dead)
+            .map(|(_, latency)| *latency)
+            .max(),
+    };
+
+    ResolveResult {
+        status,
+        latency,
+        failed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(url: &str) -> String {
+        let mut url = Url::parse(url).unwrap();
+
+        ResolveOptions::strip_from(&mut url);
+
+        url.to_string()
+    }
+
+    #[test]
+    fn it_parses_namespaced_options() {
+        let url = Url::parse("http://app/?vigil_family=v6&vigil_policy=any&family=v4").unwrap();
+        let options = ResolveOptions::parse_namespaced_from(&url, ResolvePolicy::First).unwrap();
+
+        assert_eq!(options.family, ResolveFamily::V6);
+        assert_eq!(options.policy, ResolvePolicy::Any);
+
+        let url = Url::parse("http://app/?vigil_policy=some&policy=some").unwrap();
+
+        assert!(ResolveOptions::parse_namespaced_from(&url, ResolvePolicy::First).is_err());
+    }
+
+    #[test]
+    fn it_parses_plain_options() {
+        let url = Url::parse("tcp://app:80?family=v6&policy=any").unwrap();
+        let options = ResolveOptions::parse_from(&url, ResolvePolicy::First).unwrap();
+
+        assert_eq!(options.family, ResolveFamily::V6);
+        assert_eq!(options.policy, ResolvePolicy::Any);
+
+        let url = Url::parse("icmp://app?vigil_family=v4&policy=first").unwrap();
+        let options = ResolveOptions::parse_from(&url, ResolvePolicy::All).unwrap();
+
+        assert_eq!(options.family, ResolveFamily::V4);
+        assert_eq!(options.policy, ResolvePolicy::First);
+
+        for url in ["tcp://app:80?family=v5", "icmp://app?vigil_policy=some"] {
+            assert!(
+                ResolveOptions::parse_from(&Url::parse(url).unwrap(), ResolvePolicy::All).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn it_strips_only_resolve_options() {
+        assert_eq!(
+            strip(
+                "http://app/health?family=v4&vigil_family=v4&policy=all&q=a%20b&vigil_policy=any"
+            ),
+            "http://app/health?family=v4&policy=all&q=a%20b"
+        );
+        assert_eq!(
+            strip("http://app/health?vigil_family=v4"),
+            "http://app/health"
+        );
+        assert_eq!(
+            strip("http://app/health?q=a+b&&x"),
+            "http://app/health?q=a+b&&x"
+        );
+    }
+}
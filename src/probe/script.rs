@@ -4,61 +4,504 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-use run_script::{self, ScriptOptions};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
 
 use super::report::{status as report_status, ReportReplica};
+use super::states::{record as states_record, record_report as states_record_report};
 use super::status::Status;
 use crate::config::config::{ConfigProbeService, ConfigProbeServiceNode};
+use crate::APP_CONF;
+
+const SCRIPT_SHELL: &str = "sh";
+const SCRIPT_WAIT_POLL_MILLISECONDS: u64 = 50;
+const SCRIPT_OUTPUT_MAXIMUM_SIZE: usize = 65536;
+const SCRIPT_OUTPUT_READ_SIZE: usize = 4096;
+
+pub enum ScriptEntry {
+    Inline(String),
+    Table(ScriptTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptTable {
+    pub id: Option<String>,
+    pub path: Option<PathBuf>,
+    pub source: Option<String>,
+
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    #[serde(default)]
+    pub interpreter: ScriptInterpreter,
+
+    pub cwd: Option<PathBuf>,
+
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScriptInterpreter {
+    #[default]
+    #[serde(rename = "sh")]
+    Sh,
+
+    #[serde(rename = "bash")]
+    Bash,
+
+    #[serde(rename = "python3")]
+    Python3,
+
+    #[serde(rename = "exec")]
+    Exec,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScriptOutputMode {
+    #[default]
+    #[serde(rename = "none")]
+    None,
+
+    #[serde(rename = "structured")]
+    Structured,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScriptConvention {
+    #[default]
+    #[serde(rename = "vigil")]
+    Vigil,
+
+    #[serde(rename = "nagios")]
+    Nagios,
+}
 
-pub fn dispatch(service: &ConfigProbeService, node: &ConfigProbeServiceNode, interval: u64) {
-    if let Some(ref scripts) = node.scripts {
-        if !scripts.is_empty() {
-            debug!("script node has scripts in service node: #{}", node.id);
-
-            for (index, script) in scripts.iter().enumerate() {
-                let replica_id = index.to_string();
-                let replica_status = proceed_replica(&service.id, &node.id, &replica_id, script);
-
-                debug!("got replica status upon script: {:?}", replica_status);
-
-                match report_status(
-                    &service,
-                    node,
-                    ReportReplica::Script(&replica_id),
-                    &replica_status,
-                    interval,
-                ) {
-                    Ok(_) => info!("reported script replica status: {:?}", replica_status),
-                    Err(_) => error!(
-                        "failed reporting script replica status: {:?}",
-                        replica_status
-                    ),
+#[derive(Deserialize, Debug, Default)]
+pub struct ScriptReport {
+    pub status: Option<Status>,
+    pub latency: Option<u64>,
+    pub message: Option<String>,
+
+    #[serde(skip)]
+    pub perfdata: Vec<ScriptPerfdata>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ScriptPerfdata {
+    pub label: String,
+    pub value: f64,
+    pub unit: String,
+    pub warning: Option<String>,
+    pub critical: Option<String>,
+    pub minimum: Option<f64>,
+    pub maximum: Option<f64>,
+}
+
+enum ScriptExecution {
+    Exited(ExitStatus, Vec<u8>),
+    TimedOut(Duration),
+}
+
+impl<'de> Deserialize<'de> for ScriptEntry {
+    fn deserialize<D>(de: D) -> Result<ScriptEntry, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ScriptEntryVisitor;
+
+        impl<'de> Visitor<'de> for ScriptEntryVisitor {
+            type Value = ScriptEntry;
+
+            fn expecting(&self, format: &mut fmt::Formatter) -> fmt::Result {
+                format.write_str("a shell script source or a script table")
+            }
+
+            fn visit_str<E: Error>(self, value: &str) -> Result<ScriptEntry, E> {
+                Ok(ScriptEntry::Inline(value.to_owned()))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<ScriptEntry, M::Error> {
+                ScriptTable::deserialize(MapAccessDeserializer::new(map)).map(ScriptEntry::Table)
+            }
+        }
+
+        de.deserialize_any(ScriptEntryVisitor)
+    }
+}
+
+impl ScriptEntry {
+    pub fn replica_id(&self, index: usize) -> String {
+        // Notice: the entry index is only used as a fallback, as it changes whenever scripts are \
+        //   inserted or removed, which would mix up replica histories on Vigil.
+        match self {
+            Self::Table(ScriptTable { id: Some(id), .. }) => id.to_owned(),
+            _ => index.to_string(),
+        }
+    }
+}
+
+impl ScriptReport {
+    fn parse(output: &[u8], unknown_status: Status) -> Option<ScriptReport> {
+        let output = String::from_utf8_lossy(output);
+        let mut lines = output
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty());
+
+        // Notice: JSON reports are read from the last line (so that scripts can print logs \
+        //   beforehand), while Nagios-style reports are read from the first line, as plugins do.
+        match lines.clone().next_back() {
+            Some(line) if line.starts_with('{') => Self::parse_json(line),
+            _ => lines
+                .next()
+                .and_then(|line| Self::parse_nagios(line, unknown_status)),
+        }
+    }
+
+    fn parse_plugin(output: &[u8], unknown_status: Status) -> Option<ScriptReport> {
+        // Notice: plugin status is given by the exit code, thus the status keyword is ignored.
+        String::from_utf8_lossy(output)
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .and_then(|line| Self::parse_nagios(line, unknown_status))
+            .map(|report| ScriptReport {
+                status: None,
+                ..report
+            })
+    }
+
+    fn parse_json(line: &str) -> Option<ScriptReport> {
+        serde_json::from_str(line)
+            .map_err(|err| warn!("script replica output has an invalid json report: {}", err))
+            .ok()
+    }
+
+    fn parse_nagios(line: &str, unknown_status: Status) -> Option<ScriptReport> {
+        // Split status text from performance data (eg. 'DISK OK - 42% used | time=0.1s')
+        let (text, perfdata) = match line.split_once('|') {
+            Some((text, perfdata)) => (text.trim(), ScriptPerfdata::parse_all(perfdata)),
+            None => (line, Vec::new()),
+        };
+
+        // Notice: the status keyword may be preceded by a service name (eg. 'DISK OK').
+        let status =
+            text.split_whitespace()
+                .take(2)
+                .find_map(|word| match word.trim_end_matches(':') {
+                    "OK" => Some(Status::Healthy),
+                    "WARNING" => Some(Status::Sick),
+                    "CRITICAL" => Some(Status::Dead),
+                    "UNKNOWN" => Some(unknown_status),
+                    _ => None,
+                })?;
+
+        let message = text
+            .split_once(" - ")
+            .or_else(|| text.split_once(": "))
+            .map(|(_, message)| message.trim())
+            .filter(|message| !message.is_empty())
+            .map(|message| message.to_owned());
+
+        // Acquire latency from the 'time' performance data value (eg. 'time=0.120s;;;0')
+        let latency = perfdata
+            .iter()
+            .find(|entry| entry.label == "time")
+            .and_then(|entry| entry.as_milliseconds());
+
+        Some(ScriptReport {
+            status: Some(status),
+            latency,
+            message,
+            perfdata,
+        })
+    }
+}
+
+impl ScriptPerfdata {
+    fn parse_all(perfdata: &str) -> Vec<ScriptPerfdata> {
+        let mut entries = Vec::new();
+        let mut rest = perfdata.trim_start();
+
+        // Notice: labels can be quoted, so that they can contain spaces (eg. \
+        //   'free space'=42%), in which case quotes within labels are doubled.
+        while !rest.is_empty() {
+            let (label, field) = if let Some(quoted) = rest.strip_prefix('\'') {
+                match quoted.split_once("'=") {
+                    Some((label, after_label)) => {
+                        let (field, after_field) = split_token(after_label);
+
+                        rest = after_field;
+
+                        (label.replace("''", "'"), field)
+                    }
+                    None => break,
+                }
+            } else {
+                let (token, after_token) = split_token(rest);
+
+                rest = after_token;
+
+                match token.split_once('=') {
+                    Some((label, field)) => (label.to_owned(), field),
+                    None => {
+                        debug!("script replica output has invalid perfdata: {}", token);
+
+                        continue;
+                    }
                 }
+            };
+
+            match Self::parse(label, field) {
+                Some(entry) => entries.push(entry),
+                None => debug!(
+                    "script replica output has invalid perfdata value: {}",
+                    field
+                ),
             }
+        }
+
+        entries
+    }
+
+    fn parse(label: String, field: &str) -> Option<ScriptPerfdata> {
+        // Perfdata fields are: 'value[unit];[warning];[critical];[minimum];[maximum]'
+        let mut parts = field.split(';');
+
+        let value = parts.next()?;
+        let (number, unit) = value.split_at(
+            value
+                .find(|character: char| !(character.is_ascii_digit() || ".-".contains(character)))
+                .unwrap_or(value.len()),
+        );
+
+        let mut next_part = || {
+            parts
+                .next()
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .map(|part| part.to_owned())
+        };
+
+        Some(ScriptPerfdata {
+            label,
+            value: number.parse().ok()?,
+            unit: unit.to_owned(),
+            warning: next_part(),
+            critical: next_part(),
+            minimum: next_part().and_then(|minimum| minimum.parse().ok()),
+            maximum: next_part().and_then(|maximum| maximum.parse().ok()),
+        })
+    }
+
+    fn as_milliseconds(&self) -> Option<u64> {
+        if self.value < 0.0 {
+            return None;
+        }
 
-            return;
+        match self.unit.as_str() {
+            "" | "s" => Some((self.value * 1000.0) as u64),
+            "ms" => Some(self.value as u64),
+            "us" => Some((self.value / 1000.0) as u64),
+            _ => None,
         }
     }
+}
 
-    warn!(
-        "script node has no usable script in service node: #{}",
-        node.id
+impl ScriptInterpreter {
+    fn is_shell(&self) -> bool {
+        matches!(self, Self::Sh | Self::Bash)
+    }
+
+    fn as_program(&self) -> Option<&'static str> {
+        match self {
+            Self::Sh => Some("sh"),
+            Self::Bash => Some("bash"),
+            Self::Python3 => Some("python3"),
+            Self::Exec => None,
+        }
+    }
+}
+
+pub fn dispatch(
+    service: &ConfigProbeService,
+    node: &ConfigProbeServiceNode,
+    index: usize,
+    script: &ScriptEntry,
+    interval: u64,
+) {
+    let replica_id = script.replica_id(index);
+
+    let start_time = Instant::now();
+    let (mut replica_status, replica_code, replica_report) =
+        proceed_replica(&service.id, node, &replica_id, script);
+    let mut replica_latency = start_time.elapsed();
+
+    // Notice: a latency reported by the script (eg. the duration of a request that it made) is \
+    //   used in place of the script execution time, and thus may mark the replica as sick.
+    if let Some(latency) = replica_report.as_ref().and_then(|report| report.latency) {
+        replica_latency = Duration::from_millis(latency);
+
+        if replica_status != Status::Dead
+            && replica_latency >= Duration::from_secs(APP_CONF.metrics.poll_delay_sick)
+        {
+            replica_status = Status::Sick;
+        }
+    }
+
+    debug!("got replica status upon script: {:?}", replica_status);
+
+    let report_result = report_status(
+        service,
+        node,
+        ReportReplica::Script(&replica_id),
+        &replica_status,
+        interval,
+    );
+
+    match report_result {
+        Ok(_) => info!("reported script replica status: {:?}", replica_status),
+        Err(_) => error!(
+            "failed reporting script replica status: {:?}",
+            replica_status
+        ),
+    }
+
+    states_record(
+        &service.id,
+        &node.id,
+        &replica_id,
+        &replica_status,
+        Some(replica_latency),
+        replica_code,
+        report_result.is_ok(),
     );
+
+    states_record_report(&service.id, &node.id, &replica_id, replica_report);
 }
 
-pub fn proceed_replica(service_id: &str, node_id: &str, replica_id: &str, script: &str) -> Status {
+pub fn proceed_replica(
+    service_id: &str,
+    node: &ConfigProbeServiceNode,
+    replica_id: &str,
+    script: &ScriptEntry,
+) -> (Status, Option<i32>, Option<ScriptReport>) {
     info!(
         "executing script replica on #{}:#{}:[#{}]",
-        service_id, node_id, replica_id
+        service_id, node.id, replica_id
+    );
+
+    // Notice: the global timeout is only read if the node has none (so that tests can run \
+    //   without a loaded configuration).
+    let timeout = Duration::from_secs(
+        node.script_timeout
+            .unwrap_or_else(|| APP_CONF.probe.script_timeout),
     );
 
-    match run_script::run(script, &Vec::new(), &ScriptOptions::new()) {
-        Ok((code, _, _)) => {
-            // Return code '0' goes for 'healthy', '1' goes for 'sick'; any other code is 'dead'
-            let replica_status = match code {
-                0 => Status::Healthy,
-                1 => Status::Sick,
-                _ => Status::Dead,
+    let is_structured = node.script_output == ScriptOutputMode::Structured;
+    let is_plugin = node.script_convention == ScriptConvention::Nagios;
+
+    match execute(script, timeout, is_structured || is_plugin) {
+        Ok(ScriptExecution::Exited(exit_status, output)) => {
+            let (replica_status, replica_code) = proceed_replica_exit(node, exit_status);
+
+            // Parse structured report (its status overrides the return code status)
+            let replica_report = if is_structured {
+                ScriptReport::parse(&output, node.script_unknown_status)
+            } else if is_plugin {
+                ScriptReport::parse_plugin(&output, node.script_unknown_status)
+            } else {
+                None
+            };
+
+            match replica_report {
+                Some(report) => {
+                    info!(
+                        "script replica on #{}:#{}:[#{}] reported status: {}, latency: {}, \
+                            message: {}",
+                        service_id,
+                        node.id,
+                        replica_id,
+                        report
+                            .status
+                            .map(|status| status.as_str())
+                            .unwrap_or("none"),
+                        report
+                            .latency
+                            .map(|latency| format!("{}ms", latency))
+                            .unwrap_or_else(|| "none".to_string()),
+                        report.message.as_deref().unwrap_or("none")
+                    );
+
+                    (
+                        report.status.unwrap_or(replica_status),
+                        replica_code,
+                        Some(report),
+                    )
+                }
+                None => {
+                    if is_structured {
+                        debug!("script replica output has no structured report");
+                    }
+
+                    (replica_status, replica_code, None)
+                }
+            }
+        }
+        Ok(ScriptExecution::TimedOut(elapsed)) => {
+            warn!(
+                "script replica execution timed out on #{}:#{}:[#{}] after {}ms (timeout: {}s), \
+                    killed its process group",
+                service_id,
+                node.id,
+                replica_id,
+                elapsed.as_millis(),
+                timeout.as_secs()
+            );
+
+            (Status::Dead, None, None)
+        }
+        Err(err) => {
+            error!("script replica execution failed with error: {}", err);
+
+            (Status::Dead, None, None)
+        }
+    }
+}
+
+fn proceed_replica_exit(
+    node: &ConfigProbeServiceNode,
+    exit_status: ExitStatus,
+) -> (Status, Option<i32>) {
+    match exit_status.code() {
+        Some(code) => {
+            let replica_status = match (node.script_convention, code) {
+                // Return code '0' goes for 'healthy', '1' goes for 'sick'; any other code is 'dead'
+                (ScriptConvention::Vigil, 0) => Status::Healthy,
+                (ScriptConvention::Vigil, 1) => Status::Sick,
+                (ScriptConvention::Vigil, _) => Status::Dead,
+
+                // Return code '0' goes for 'OK', '1' for 'WARNING', '2' for 'CRITICAL' and '3' \
+                //   for 'UNKNOWN' (any other code is 'UNKNOWN' as well, as Nagios does)
+                (ScriptConvention::Nagios, 0) => Status::Healthy,
+                (ScriptConvention::Nagios, 1) => Status::Sick,
+                (ScriptConvention::Nagios, 2) => Status::Dead,
+                (ScriptConvention::Nagios, _) => node.script_unknown_status,
             };
 
             if replica_status == Status::Dead {
@@ -73,12 +516,671 @@ pub fn proceed_replica(service_id: &str, node_id: &str, replica_id: &str, script
                 );
             }
 
-            replica_status
+            (replica_status, Some(code))
         }
-        Err(err) => {
-            error!("script replica execution failed with error: {}", err);
+        None => {
+            warn!(
+                "script replica execution was terminated by signal: {}",
+                exit_status.signal().unwrap_or(0)
+            );
+
+            (Status::Dead, None)
+        }
+    }
+}
+
+pub fn resolve(node: &mut ConfigProbeServiceNode, directory: &Path) -> Result<(), String> {
+    let mut replica_ids = HashSet::new();
+
+    for (index, script) in node.scripts.iter_mut().flatten().enumerate() {
+        // Check replica identifier (it must be unique within the node)
+        let replica_id = script.replica_id(index);
+
+        if replica_id.is_empty() {
+            return Err("script id must not be empty".to_string());
+        }
+
+        if !replica_ids.insert(replica_id.to_owned()) {
+            return Err(format!("script id: {} is used more than once", replica_id));
+        }
+
+        if let ScriptEntry::Table(ref mut table) = script {
+            match (&mut table.path, &table.source) {
+                (Some(ref mut path), None) => {
+                    // Notice: script paths are made absolute, as they would otherwise be \
+                    //   relative to the script working directory (if any is set).
+                    let full_path = directory.join(&path);
+
+                    *path = fs::canonicalize(&full_path).map_err(|err| {
+                        format!(
+                            "script file: {} cannot be found ({})",
+                            full_path.display(),
+                            err
+                        )
+                    })?;
+
+                    let metadata = fs::metadata(&path).map_err(|err| err.to_string())?;
+
+                    if !metadata.is_file() {
+                        return Err(format!("script: {} is not a file", path.display()));
+                    }
+
+                    if table.interpreter == ScriptInterpreter::Exec
+                        && metadata.permissions().mode() & 0o111 == 0
+                    {
+                        return Err(format!("script file: {} is not executable", path.display()));
+                    }
+                }
+                (None, Some(_)) => {
+                    if table.interpreter == ScriptInterpreter::Exec {
+                        return Err("script source cannot be run with the exec interpreter".into());
+                    }
+                }
+                _ => return Err("script must have either a path or a source".to_string()),
+            }
+
+            if let Some(ref mut cwd) = table.cwd {
+                let full_cwd = directory.join(&cwd);
+
+                *cwd = fs::canonicalize(&full_cwd)
+                    .ok()
+                    .filter(|cwd| cwd.is_dir())
+                    .ok_or_else(|| {
+                        format!("script cwd: {} is not a directory", full_cwd.display())
+                    })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn execute(
+    script: &ScriptEntry,
+    timeout: Duration,
+    capture_output: bool,
+) -> Result<ScriptExecution, io::Error> {
+    let start_time = Instant::now();
+
+    let mut command = match script {
+        ScriptEntry::Inline(source) => {
+            let mut command = Command::new(SCRIPT_SHELL);
+
+            command.arg("-c").arg(source);
+
+            command
+        }
+        ScriptEntry::Table(table) => {
+            let mut command = match (&table.path, &table.source) {
+                (Some(path), _) => {
+                    debug!(
+                        "script replica will run file: {} with interpreter: {:?}",
+                        path.display(),
+                        table.interpreter
+                    );
+
+                    match table.interpreter.as_program() {
+                        Some(program) => {
+                            let mut command = Command::new(program);
+
+                            command.arg(path);
+
+                            command
+                        }
+                        None => Command::new(path),
+                    }
+                }
+                (None, Some(source)) => {
+                    let program = table.interpreter.as_program().unwrap_or(SCRIPT_SHELL);
+                    let mut command = Command::new(program);
+
+                    command.arg("-c").arg(source);
+
+                    // Shells take the script name as first argument after the source
+                    if table.interpreter.is_shell() {
+                        command.arg(program);
+                    }
+
+                    command
+                }
+                (None, None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "script has no path or source",
+                    ))
+                }
+            };
+
+            command.args(&table.args).envs(&table.env);
+
+            if let Some(ref cwd) = table.cwd {
+                command.current_dir(cwd);
+            }
+
+            command
+        }
+    };
+
+    // Notice: the script is spawned in its own process group, so that all the processes that \
+    //   it spawned (eg. a hung 'curl') can be killed along with it upon timeout.
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(if capture_output {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+
+    // Notice: the output pipe is read without blocking while waiting for the script, as a \
+    //   sub-process left running in the background could otherwise hold it open forever.
+    let mut stdout = child.stdout.take();
+    let mut output = Vec::new();
+
+    if let Some(ref stdout) = stdout {
+        if let Err(err) = set_nonblocking(stdout) {
+            kill(&mut child);
+
+            return Err(err);
+        }
+    }
+
+    // Wait for script to exit (or for its timeout to expire)
+    loop {
+        if let Some(ref mut stdout) = stdout {
+            read_output(stdout, &mut output);
+        }
+
+        match child.try_wait() {
+            Ok(Some(exit_status)) => {
+                // Read any output left in the pipe after exit
+                if let Some(ref mut stdout) = stdout {
+                    read_output(stdout, &mut output);
+                }
+
+                return Ok(ScriptExecution::Exited(exit_status, output));
+            }
+            Ok(None) => {
+                let elapsed = start_time.elapsed();
+
+                if elapsed >= timeout {
+                    kill(&mut child);
+
+                    return Ok(ScriptExecution::TimedOut(elapsed));
+                }
+
+                thread::sleep(
+                    (timeout - elapsed).min(Duration::from_millis(SCRIPT_WAIT_POLL_MILLISECONDS)),
+                );
+            }
+            Err(err) => {
+                kill(&mut child);
+
+                return Err(err);
+            }
+        }
+    }
+}
+
+fn set_nonblocking(stdout: &ChildStdout) -> Result<(), io::Error> {
+    let descriptor = stdout.as_raw_fd();
+
+    let result = unsafe {
+        let flags = libc::fcntl(descriptor, libc::F_GETFL);
+
+        if flags < 0 {
+            flags
+        } else {
+            libc::fcntl(descriptor, libc::F_SETFL, flags | libc::O_NONBLOCK)
+        }
+    };
+
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn read_output(stdout: &mut ChildStdout, output: &mut Vec<u8>) {
+    let mut buffer = [0; SCRIPT_OUTPUT_READ_SIZE];
+
+    // Notice: output past the maximum size is drained and discarded, so that the script does \
+    //   not block on a full pipe.
+    loop {
+        match stdout.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(size) => {
+                let remaining = SCRIPT_OUTPUT_MAXIMUM_SIZE.saturating_sub(output.len());
+
+                output.extend_from_slice(&buffer[..size.min(remaining)]);
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+}
+
+fn split_token(value: &str) -> (&str, &str) {
+    match value.split_once(char::is_whitespace) {
+        Some((token, rest)) => (token, rest.trim_start()),
+        None => (value, ""),
+    }
+}
+
+fn kill(child: &mut Child) {
+    // Kill whole process group (its identifier is the script process identifier)
+    let result = unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+
+    if result != 0 {
+        warn!(
+            "could not kill script process group: {} (error: {})",
+            child.id(),
+            io::Error::last_os_error()
+        );
+    }
+
+    // Reap script process (so that it does not linger as a zombie)
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::test_node;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("vigil-local-{}-{}", std::process::id(), name));
+
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn script_file(directory: &Path, name: &str, mode: u32) {
+        let path = directory.join(name);
+
+        fs::write(&path, "exit 0\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn script_node(scripts: &str) -> ConfigProbeServiceNode {
+        test_node("script", &format!("scripts = [{}]", scripts))
+    }
+
+    fn exit(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn it_parses_json_report_from_last_line() {
+        let report = ScriptReport::parse(
+            b"starting check\n{\"status\": \"sick\", \"latency\": 120, \"message\": \"slow\"}\n\n",
+            Status::Dead,
+        )
+        .unwrap();
+
+        assert_eq!(report.status, Some(Status::Sick));
+        assert_eq!(report.latency, Some(120));
+        assert_eq!(report.message.as_deref(), Some("slow"));
+    }
+
+    #[test]
+    fn it_parses_partial_json_report() {
+        let report = ScriptReport::parse(b"{\"latency\": 5}", Status::Dead).unwrap();
+
+        assert_eq!(report.status, None);
+        assert_eq!(report.latency, Some(5));
+        assert_eq!(report.message, None);
+    }
+
+    #[test]
+    fn it_falls_back_to_exit_code_upon_malformed_json() {
+        let node = test_node(
+            "script",
+            "script_output = \"structured\"\nscript_timeout = 10",
+        );
+
+        assert!(
+            ScriptReport::parse(b"OK - all good\n{\"status\": \"healthy\"", Status::Dead).is_none()
+        );
+        assert!(ScriptReport::parse(b"{\"status\": \"unknown\"}", Status::Dead).is_none());
+        assert!(ScriptReport::parse(b"", Status::Dead).is_none());
+
+        let script = ScriptEntry::Inline(
+            "echo 'OK - all good'; echo '{\"status\": \"healthy\"'; exit 1".to_string(),
+        );
+
+        let (status, code, report) = proceed_replica("service", &node, "0", &script);
+
+        assert_eq!((status, code), (Status::Sick, Some(1)));
+        assert!(report.is_none());
+
+        // Well-formed report status overrides the exit code status
+        let script = ScriptEntry::Inline("echo '{\"status\": \"healthy\"}'; exit 1".to_string());
+
+        let (status, code, report) = proceed_replica("service", &node, "0", &script);
+
+        assert_eq!((status, code), (Status::Healthy, Some(1)));
+        assert_eq!(report.unwrap().status, Some(Status::Healthy));
+    }
+
+    #[test]
+    fn it_maps_vigil_exit_codes() {
+        let node = test_node("script", "");
+
+        assert_eq!(
+            proceed_replica_exit(&node, exit(0)),
+            (Status::Healthy, Some(0))
+        );
+        assert_eq!(
+            proceed_replica_exit(&node, exit(1)),
+            (Status::Sick, Some(1))
+        );
+        assert_eq!(
+            proceed_replica_exit(&node, exit(2)),
+            (Status::Dead, Some(2))
+        );
+        assert_eq!(
+            proceed_replica_exit(&node, exit(3)),
+            (Status::Dead, Some(3))
+        );
+
+        // Killed by a signal
+        assert_eq!(
+            proceed_replica_exit(&node, ExitStatus::from_raw(libc::SIGKILL)),
+            (Status::Dead, None)
+        );
+    }
+
+    #[test]
+    fn it_maps_nagios_exit_codes() {
+        let sick_node = test_node(
+            "script",
+            "script_convention = \"nagios\"\nscript_unknown_status = \"sick\"",
+        );
+
+        assert_eq!(
+            proceed_replica_exit(&sick_node, exit(0)),
+            (Status::Healthy, Some(0))
+        );
+        assert_eq!(
+            proceed_replica_exit(&sick_node, exit(1)),
+            (Status::Sick, Some(1))
+        );
+        assert_eq!(
+            proceed_replica_exit(&sick_node, exit(2)),
+            (Status::Dead, Some(2))
+        );
+        assert_eq!(
+            proceed_replica_exit(&sick_node, exit(3)),
+            (Status::Sick, Some(3))
+        );
+
+        // Out-of-range codes are considered as 'UNKNOWN'
+        assert_eq!(
+            proceed_replica_exit(&sick_node, exit(4)),
+            (Status::Sick, Some(4))
+        );
+        assert_eq!(
+            proceed_replica_exit(&sick_node, exit(255)),
+            (Status::Sick, Some(255))
+        );
+
+        let dead_node = test_node("script", "script_convention = \"nagios\"");
+
+        assert_eq!(
+            proceed_replica_exit(&dead_node, exit(3)),
+            (Status::Dead, Some(3))
+        );
+        assert_eq!(
+            proceed_replica_exit(&dead_node, exit(126)),
+            (Status::Dead, Some(126))
+        );
+    }
+
+    #[test]
+    fn it_parses_nagios_line() {
+        let report = ScriptReport::parse(
+            b"DISK WARNING - free space: / 3326 MB (9%) | /=2643MB;5948;5958;0;5968 time=0.12s\n\
+                second line",
+            Status::Dead,
+        )
+        .unwrap();
+
+        assert_eq!(report.status, Some(Status::Sick));
+        assert_eq!(report.latency, Some(120));
+        assert_eq!(
+            report.message.as_deref(),
+            Some("free space: / 3326 MB (9%)")
+        );
+        assert_eq!(report.perfdata.len(), 2);
+
+        let report = ScriptReport::parse(b"UNKNOWN: no data", Status::Healthy).unwrap();
+
+        assert_eq!(report.status, Some(Status::Healthy));
+        assert_eq!(report.message.as_deref(), Some("no data"));
+
+        assert!(ScriptReport::parse(b"all good", Status::Dead).is_none());
+    }
+
+    #[test]
+    fn it_ignores_plugin_status_keyword() {
+        let report = ScriptReport::parse_plugin(
+            b"LOAD CRITICAL - load average: 12.0 | load1=12.0;5;10;0",
+            Status::Dead,
+        )
+        .unwrap();
+
+        assert_eq!(report.status, None);
+        assert_eq!(report.message.as_deref(), Some("load average: 12.0"));
+        assert_eq!(report.perfdata[0].label, "load1");
+    }
+
+    #[test]
+    fn it_parses_quoted_perfdata_labels() {
+        let perfdata = ScriptPerfdata::parse_all("'free space'=42% 'it''s'=1 plain=2");
+
+        assert_eq!(perfdata.len(), 3);
+        assert_eq!(perfdata[0].label, "free space");
+        assert_eq!(perfdata[0].value, 42.0);
+        assert_eq!(perfdata[0].unit, "%");
+        assert_eq!(perfdata[1].label, "it's");
+        assert_eq!(perfdata[2].label, "plain");
+    }
+
+    #[test]
+    fn it_parses_perfdata_units_and_missing_fields() {
+        let perfdata = ScriptPerfdata::parse_all("rta=0.5ms;;100.0;0; bytes=1024B time=-1.5s");
+
+        assert_eq!(perfdata.len(), 3);
+
+        assert_eq!(perfdata[0].value, 0.5);
+        assert_eq!(perfdata[0].unit, "ms");
+        assert_eq!(perfdata[0].warning, None);
+        assert_eq!(perfdata[0].critical.as_deref(), Some("100.0"));
+        assert_eq!(perfdata[0].minimum, Some(0.0));
+        assert_eq!(perfdata[0].maximum, None);
+        assert_eq!(perfdata[0].as_milliseconds(), Some(0));
+
+        assert_eq!(perfdata[1].unit, "B");
+        assert_eq!(perfdata[1].warning, None);
+        assert_eq!(perfdata[1].minimum, None);
+        assert_eq!(perfdata[1].as_milliseconds(), None);
+
+        assert_eq!(perfdata[2].value, -1.5);
+        assert_eq!(perfdata[2].as_milliseconds(), None);
+    }
+
+    #[test]
+    fn it_skips_invalid_perfdata() {
+        let perfdata = ScriptPerfdata::parse_all("novalue label=abc 'unterminated=1");
+
+        assert!(perfdata.is_empty());
+    }
+
+    #[test]
+    fn it_kills_process_group_upon_timeout() {
+        let directory = directory("timeout");
+
+        // Spawn a sleeping child, which would leave a marker file if it outlived the timeout
+        let (pid_path, marker_path) = (directory.join("pid"), directory.join("marker"));
+        let script = ScriptEntry::Inline(format!(
+            "echo $$ > {}; (sleep 1; touch {}) & wait",
+            pid_path.display(),
+            marker_path.display()
+        ));
+
+        let execution = execute(&script, Duration::from_millis(200), false).unwrap();
+
+        assert!(matches!(execution, ScriptExecution::TimedOut(_)));
+
+        let pgid = fs::read_to_string(&pid_path).unwrap().trim().to_string();
+
+        thread::sleep(Duration::from_millis(1500));
+
+        // Notice: killed processes may linger as zombies until reaped by init, which is fine.
+        let processes = Command::new("ps")
+            .args(["-A", "-o", "pgid=,stat="])
+            .output()
+            .unwrap();
+
+        let is_group_alive = String::from_utf8_lossy(&processes.stdout)
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .any(|fields| fields[0] == pgid && !fields[1].starts_with('Z'));
+
+        assert!(!is_group_alive);
+        assert!(!marker_path.exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn it_resolves_relative_paths_from_config_directory() {
+        let directory = directory("relative");
+
+        script_file(&directory, "check.sh", 0o755);
+        fs::create_dir_all(directory.join("work")).unwrap();
+
+        let mut node = script_node("{ path = \"check.sh\", cwd = \"work\" }");
+
+        resolve(&mut node, &directory).unwrap();
+
+        match node.scripts.as_deref() {
+            Some([ScriptEntry::Table(table)]) => {
+                assert_eq!(
+                    table.path,
+                    Some(fs::canonicalize(directory.join("check.sh")).unwrap())
+                );
+                assert_eq!(
+                    table.cwd,
+                    Some(fs::canonicalize(directory.join("work")).unwrap())
+                );
+            }
+            _ => panic!("invalid scripts"),
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_missing_script_file_or_cwd() {
+        let directory = directory("missing");
+
+        script_file(&directory, "check.sh", 0o755);
+
+        let mut node = script_node("{ path = \"missing.sh\" }");
+
+        assert!(resolve(&mut node, &directory)
+            .unwrap_err()
+            .contains("cannot be found"));
+
+        let mut node = script_node("{ path = \"check.sh\", cwd = \"missing\" }");
+
+        assert!(resolve(&mut node, &directory)
+            .unwrap_err()
+            .contains("is not a directory"));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_non_executable_exec_script() {
+        let directory = directory("executable");
+
+        script_file(&directory, "check.sh", 0o644);
+
+        let mut node = script_node("{ path = \"check.sh\", interpreter = \"exec\" }");
+
+        assert!(resolve(&mut node, &directory)
+            .unwrap_err()
+            .contains("is not executable"));
+
+        // Interpreted scripts do not need to be executable
+        let mut node = script_node("{ path = \"check.sh\", interpreter = \"sh\" }");
+
+        assert!(resolve(&mut node, &directory).is_ok());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_invalid_script_sources() {
+        let directory = directory("source");
+
+        let mut node = script_node("{ source = \"exit 0\", interpreter = \"exec\" }");
+
+        assert!(resolve(&mut node, &directory)
+            .unwrap_err()
+            .contains("exec interpreter"));
+
+        script_file(&directory, "check.sh", 0o755);
+
+        for scripts in [
+            "{ id = \"a\" }",
+            "{ path = \"check.sh\", source = \"exit 0\" }",
+        ] {
+            assert!(resolve(&mut script_node(scripts), &directory)
+                .unwrap_err()
+                .contains("either a path or a source"));
+        }
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn it_rejects_duplicate_or_empty_script_ids() {
+        // Explicit identifiers, and index fallback identifiers
+        let mut node = script_node("\"exit 0\", { id = \"web\", source = \"exit 0\" }");
+
+        assert!(resolve(&mut node, Path::new("")).is_ok());
 
-            Status::Dead
+        for (scripts, error) in [
+            (
+                "{ id = \"web\", source = \"exit 0\" }, { id = \"web\", source = \"exit 1\" }",
+                "script id: web is used more than once",
+            ),
+            (
+                "\"exit 0\", { id = \"0\", source = \"exit 1\" }",
+                "script id: 0 is used more than once",
+            ),
+            (
+                "{ id = \"1\", source = \"exit 0\" }, \"exit 1\"",
+                "script id: 1 is used more than once",
+            ),
+            (
+                "{ id = \"\", source = \"exit 0\" }",
+                "script id must not be empty",
+            ),
+        ] {
+            assert_eq!(
+                resolve(&mut script_node(scripts), Path::new("")),
+                Err(error.to_string())
+            );
         }
     }
 }
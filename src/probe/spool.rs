@@ -0,0 +1,517 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use crate::APP_CONF;
+
+const SPOOL_COMPACT_SLACK_RATIO: usize = 10;
+
+lazy_static! {
+    static ref SPOOL_STATE: Mutex<SpoolState> = Mutex::new(SpoolState::default());
+}
+
+#[derive(Default)]
+struct SpoolState {
+    entries: Option<Vec<SpoolEntry>>,
+    replaying: HashMap<String, HashSet<SpoolKey>>,
+}
+
+type SpoolKey = (String, String, String);
+
+struct SpoolReplayGuard<'a> {
+    group: &'a str,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SpoolReplay {
+    #[serde(rename = "all")]
+    All,
+
+    #[serde(rename = "latest")]
+    Latest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpoolEntry {
+    pub timestamp: u64,
+
+    #[serde(default)]
+    pub group: String,
+
+    pub service: String,
+    pub node: String,
+    pub replica: String,
+    pub health: String,
+    pub interval: u64,
+}
+
+impl SpoolState {
+    fn entries(&mut self, spool_path: &Path) -> &mut Vec<SpoolEntry> {
+        // Notice: the spool is only read from disk once, then tracked in memory, so that it \
+        //   does not get read and parsed again upon each report.
+        self.entries.get_or_insert_with(|| read(spool_path))
+    }
+}
+
+impl SpoolEntry {
+    pub fn new(
+        group: &str,
+        service: &str,
+        node: &str,
+        replica: &str,
+        health: &str,
+        interval: u64,
+    ) -> Self {
+        SpoolEntry {
+            timestamp: now(),
+            group: group.to_owned(),
+            service: service.to_owned(),
+            node: node.to_owned(),
+            replica: replica.to_owned(),
+            health: health.to_owned(),
+            interval,
+        }
+    }
+
+    fn key(&self) -> (&str, &str, &str, &str) {
+        (&self.group, &self.service, &self.node, &self.replica)
+    }
+
+    fn replica_key(&self) -> SpoolKey {
+        (
+            self.service.to_owned(),
+            self.node.to_owned(),
+            self.replica.to_owned(),
+        )
+    }
+}
+
+pub fn append(entry: SpoolEntry) {
+    if let Some(ref spool_path) = APP_CONF.report.spool_path {
+        let mut state = lock();
+
+        if write_line(spool_path, &entry).is_err() {
+            state.entries = None;
+
+            return;
+        }
+
+        let entries = state.entries(spool_path);
+
+        entries.push(entry);
+
+        // Enforce spool bounds (compact the spool, dropping oldest entries first)
+        // Notice: the spool is only compacted once it overflows by a few entries, so that it \
+        //   does not get fully rewritten upon each failed report once full. Overflowing entries \
+        //   are dropped upon replay anyway.
+        let max_entries = APP_CONF.report.spool_max_entries;
+
+        if entries.len() > max_entries + (max_entries / SPOOL_COMPACT_SLACK_RATIO).max(1) {
+            *entries = expire_from_config(mem::take(entries));
+
+            if write(spool_path, entries).is_err() {
+                state.entries = None;
+
+                return;
+            }
+        }
+
+        info!(
+            "spooled failed report, spool now holds {} reports",
+            entries.len()
+        );
+    }
+}
+
+pub fn supersede(group: &str, service: &str, node: &str, replica: &str) {
+    // Notice: a report sent while spooled reports are being replayed to the same group is newer \
+    //   than any spooled report for the same replica, which must therefore not be replayed \
+    //   afterwards, as this would overwrite the newer status with a stale one.
+    if APP_CONF.report.spool_path.is_some() {
+        if let Some(superseded) = lock().replaying.get_mut(group) {
+            superseded.insert((service.to_owned(), node.to_owned(), replica.to_owned()));
+        }
+    }
+}
+
+pub fn replay<F>(group: &str, sender: F)
+where
+    F: Fn(&SpoolEntry) -> Result<(), ()>,
+{
+    if let Some(ref spool_path) = APP_CONF.report.spool_path {
+        // Snapshot entries to replay for this group (other groups replay their own entries)
+        // Notice: the lock is not held while delivering entries, as this may take a while if \
+        //   the spool is large, which would otherwise block all other reports meanwhile. A \
+        //   single replay runs per group at once, so that entries do not get delivered twice.
+        let snapshot: Vec<SpoolEntry> = {
+            let mut state = lock();
+
+            if state.replaying.contains_key(group) {
+                debug!(
+                    "spooled reports are already being replayed to group: {}",
+                    group
+                );
+
+                return;
+            }
+
+            let entries = state.entries(spool_path);
+
+            *entries = expire_from_config(mem::take(entries));
+
+            let entries: Vec<SpoolEntry> = entries
+                .iter()
+                .filter(|entry| entry.group == group || entry.group.is_empty())
+                .cloned()
+                .collect();
+
+            if entries.is_empty() {
+                return;
+            }
+
+            state.replaying.insert(group.to_owned(), HashSet::new());
+
+            entries
+        };
+
+        let _guard = SpoolReplayGuard { group };
+
+        let entries = if APP_CONF.report.spool_replay == SpoolReplay::Latest {
+            collapse(snapshot.clone())
+        } else {
+            snapshot.clone()
+        };
+
+        info!(
+            "replaying {} spooled reports to group: {}",
+            entries.len(),
+            group
+        );
+
+        // Deliver entries in order (stop at first failure)
+        let (mut processed, mut delivered) = (0, 0);
+
+        for entry in &entries {
+            let is_superseded = lock()
+                .replaying
+                .get(group)
+                .map(|superseded| superseded.contains(&entry.replica_key()))
+                .unwrap_or(false);
+
+            if is_superseded {
+                debug!(
+                    "dropping spooled report superseded by a newer report to group: {}",
+                    group
+                );
+            } else if sender(entry).is_ok() {
+                delivered += 1;
+            } else {
+                warn!(
+                    "could not replay spooled report to group: {}, will retry later ({} reports \
+                        remaining)",
+                    group,
+                    entries.len() - processed
+                );
+
+                break;
+            }
+
+            processed += 1;
+        }
+
+        if processed == 0 {
+            return;
+        }
+
+        // Acquire snapshot entries that got processed (or superseded by a processed entry)
+        let processed_entries: Vec<&SpoolEntry> =
+            if APP_CONF.report.spool_replay == SpoolReplay::Latest {
+                let processed_keys: HashSet<_> =
+                    entries[..processed].iter().map(SpoolEntry::key).collect();
+
+                snapshot
+                    .iter()
+                    .filter(|entry| processed_keys.contains(&entry.key()))
+                    .collect()
+            } else {
+                snapshot[..processed].iter().collect()
+            };
+
+        // Drop processed entries from spool (entries spooled meanwhile are kept)
+        let mut state = lock();
+
+        let entries = state.entries(spool_path);
+
+        *entries = expire_from_config(remove(mem::take(entries), &processed_entries));
+
+        if write(spool_path, entries).is_ok() {
+            info!("replayed {} spooled reports to group: {}", delivered, group);
+        } else {
+            state.entries = None;
+        }
+    }
+}
+
+impl Drop for SpoolReplayGuard<'_> {
+    fn drop(&mut self) {
+        lock().replaying.remove(self.group);
+    }
+}
+
+fn lock() -> MutexGuard<'static, SpoolState> {
+    // Notice: a poisoned lock is recovered, as the spool state is only a cache of the spool file.
+    SPOOL_STATE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write_line(spool_path: &Path, entry: &SpoolEntry) -> Result<(), ()> {
+    let line = serde_json::to_string(entry).expect("invalid spool entry");
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(spool_path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|err| {
+            error!(
+                "could not append to report spool: {} (error: {})",
+                spool_path.display(),
+                err
+            );
+        })
+}
+
+fn read(spool_path: &Path) -> Vec<SpoolEntry> {
+    match File::open(spool_path) {
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn write(spool_path: &Path, entries: &[SpoolEntry]) -> Result<(), ()> {
+    // Write to a temporary file first, then move it over, so that the spool is never left \
+    //   half-written if the daemon gets stopped while writing.
+    let spool_path_temporary = spool_path.with_extension("tmp");
+
+    let result = File::create(&spool_path_temporary).and_then(|mut file| {
+        for entry in entries {
+            let line = serde_json::to_string(entry).expect("invalid spool entry");
+
+            writeln!(file, "{}", line)?;
+        }
+
+        file.sync_all()
+    });
+
+    match result.and_then(|_| fs::rename(&spool_path_temporary, spool_path)) {
+        Ok(_) => Ok(()),
+        Err(err) => {
+            error!(
+                "could not write report spool: {} (error: {})",
+                spool_path.display(),
+                err
+            );
+
+            Err(())
+        }
+    }
+}
+
+fn expire_from_config(entries: Vec<SpoolEntry>) -> Vec<SpoolEntry> {
+    expire(
+        entries,
+        now(),
+        APP_CONF.report.spool_max_age,
+        APP_CONF.report.spool_max_entries,
+    )
+}
+
+fn expire(
+    mut entries: Vec<SpoolEntry>,
+    now: u64,
+    max_age: u64,
+    max_entries: usize,
+) -> Vec<SpoolEntry> {
+    // Drop entries that are too old
+    entries.retain(|entry| now.saturating_sub(entry.timestamp) <= max_age);
+
+    // Drop oldest entries if the spool is full
+    if entries.len() > max_entries {
+        let overflow = entries.len() - max_entries;
+
+        warn!("report spool is full, dropping {} oldest reports", overflow);
+
+        entries.drain(0..overflow);
+    }
+
+    entries
+}
+
+fn collapse(entries: Vec<SpoolEntry>) -> Vec<SpoolEntry> {
+    let mut collapsed: Vec<SpoolEntry> = Vec::new();
+
+    // Only keep the latest entry for each replica (while preserving the order of the latest)
+    for entry in entries {
+        collapsed.retain(|existing| existing.key() != entry.key());
+
+        collapsed.push(entry);
+    }
+
+    collapsed
+}
+
+fn remove(entries: Vec<SpoolEntry>, removed_entries: &[&SpoolEntry]) -> Vec<SpoolEntry> {
+    let mut removed_counts: HashMap<&SpoolEntry, usize> = HashMap::new();
+
+    for entry in removed_entries {
+        *removed_counts.entry(entry).or_insert(0) += 1;
+    }
+
+    // Notice: identical entries may have been spooled multiple times (eg. a replica failing \
+    //   twice in the same second), therefore only as many entries as removed get dropped.
+    entries
+        .into_iter()
+        .filter(|entry| match removed_counts.get_mut(entry) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+
+                false
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u64, replica: &str, health: &str) -> SpoolEntry {
+        SpoolEntry {
+            timestamp,
+            group: "failover".to_owned(),
+            service: "web".to_owned(),
+            node: "api".to_owned(),
+            replica: replica.to_owned(),
+            health: health.to_owned(),
+            interval: 60,
+        }
+    }
+
+    fn replicas(entries: &[SpoolEntry]) -> Vec<(&str, &str)> {
+        entries
+            .iter()
+            .map(|entry| (entry.replica.as_str(), entry.health.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn it_expires_entries_older_than_max_age() {
+        let entries = vec![
+            entry(100, "a", "dead"),
+            entry(500, "b", "dead"),
+            entry(1000, "c", "dead"),
+        ];
+
+        assert_eq!(
+            replicas(&expire(entries, 1000, 500, 10)),
+            vec![("b", "dead"), ("c", "dead")]
+        );
+    }
+
+    #[test]
+    fn it_expires_oldest_entries_upon_overflow() {
+        let entries = vec![
+            entry(1000, "a", "dead"),
+            entry(1000, "b", "dead"),
+            entry(1000, "c", "dead"),
+        ];
+
+        assert_eq!(
+            replicas(&expire(entries, 1000, 500, 2)),
+            vec![("b", "dead"), ("c", "dead")]
+        );
+    }
+
+    #[test]
+    fn it_collapses_entries_to_latest_per_replica() {
+        let mut other_group = entry(3, "a", "sick");
+
+        other_group.group = "https://status.example.com".to_owned();
+
+        let entries = vec![
+            entry(1, "a", "dead"),
+            entry(2, "b", "dead"),
+            other_group,
+            entry(4, "a", "healthy"),
+            entry(5, "c", "sick"),
+        ];
+
+        let collapsed = collapse(entries);
+
+        // Notice: the order of the latest entry for each replica is preserved.
+        assert_eq!(
+            replicas(&collapsed),
+            vec![
+                ("b", "dead"),
+                ("a", "sick"),
+                ("a", "healthy"),
+                ("c", "sick")
+            ]
+        );
+        assert_eq!(collapsed[1].group, "https://status.example.com");
+    }
+
+    #[test]
+    fn it_removes_as_many_entries_as_processed() {
+        let (first, second) = (entry(1, "a", "dead"), entry(1, "b", "dead"));
+
+        // Notice: identical entries may be spooled more than once, eg. a duplicate got spooled \
+        //   while the first one was being replayed.
+        let entries = vec![
+            first.clone(),
+            second.clone(),
+            first.clone(),
+            entry(2, "a", "healthy"),
+        ];
+
+        let remaining = remove(entries, &[&first, &second]);
+
+        assert_eq!(replicas(&remaining), vec![("a", "dead"), ("a", "healthy")]);
+    }
+
+    #[test]
+    fn it_keeps_entries_not_processed() {
+        let entries = vec![entry(1, "a", "dead"), entry(2, "b", "dead")];
+
+        assert_eq!(remove(entries.clone(), &[]), entries);
+        assert_eq!(
+            remove(entries, &[&entry(3, "a", "dead")]),
+            vec![entry(1, "a", "dead"), entry(2, "b", "dead")]
+        );
+    }
+}
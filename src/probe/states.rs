@@ -0,0 +1,274 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::icmp::ICMPStats;
+use super::script::{ScriptPerfdata, ScriptReport};
+use super::status::Status;
+
+lazy_static! {
+    pub static ref PROBE_STATES: RwLock<States> = RwLock::new(States::new());
+}
+
+#[derive(Serialize)]
+pub struct States {
+    pub started: u64,
+    pub services: BTreeMap<String, StatesService>,
+}
+
+#[derive(Serialize, Default)]
+pub struct StatesService {
+    pub nodes: BTreeMap<String, StatesServiceNode>,
+}
+
+#[derive(Serialize, Default)]
+pub struct StatesServiceNode {
+    pub interval: u64,
+    pub last_run: Option<u64>,
+    pub last_run_duration: Option<u64>,
+    pub replicas: BTreeMap<String, StatesServiceNodeReplica>,
+}
+
+#[derive(Serialize, Default)]
+pub struct StatesServiceNodeReplica {
+    pub status: Option<Status>,
+    pub latency: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub message: Option<String>,
+    pub perfdata: Option<Vec<ScriptPerfdata>>,
+    pub last_check: Option<u64>,
+    pub failures: u32,
+    pub reported: Option<bool>,
+    pub report_attempts: u64,
+    pub report_failures: u64,
+    pub report_targets: BTreeMap<String, StatesServiceNodeReplicaReportTarget>,
+    pub icmp: Option<StatesServiceNodeReplicaICMP>,
+    pub failed_addresses: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Default)]
+pub struct StatesServiceNodeReplicaReportTarget {
+    pub reported: Option<bool>,
+    pub attempts: u64,
+    pub failures: u64,
+}
+
+#[derive(Serialize)]
+pub struct StatesServiceNodeReplicaICMP {
+    pub sent: u16,
+    pub received: u16,
+    pub loss: u8,
+    pub rtt_average: Option<u64>,
+    pub rtt_maximum: Option<u64>,
+    pub jitter: Option<u64>,
+}
+
+impl States {
+    fn new() -> States {
+        States {
+            started: now(),
+            services: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_stuck(&self) -> bool {
+        let now = now();
+
+        // Notice: the daemon is considered as stuck if any node did not complete a run in \
+        //   twice its interval (counting from startup time if it never completed a run).
+        self.services.values().any(|service| {
+            service.nodes.values().any(|node| {
+                let last_run = node.last_run.unwrap_or(self.started);
+
+                now.saturating_sub(last_run) > node.interval * 2
+            })
+        })
+    }
+}
+
+pub fn register(service_id: &str, node_id: &str, interval: u64) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        states
+            .services
+            .entry(service_id.to_owned())
+            .or_default()
+            .nodes
+            .entry(node_id.to_owned())
+            .or_default()
+            .interval = interval;
+    }
+}
+
+pub fn complete(service_id: &str, node_id: &str, duration: Duration) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            node.last_run = Some(now());
+            node.last_run_duration = Some(duration.as_millis() as u64);
+        }
+    }
+}
+
+pub fn record(
+    service_id: &str,
+    node_id: &str,
+    replica_id: &str,
+    status: &Status,
+    latency: Option<Duration>,
+    exit_code: Option<i32>,
+    reported: bool,
+) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            let replica = node.replicas.entry(replica_id.to_owned()).or_default();
+
+            // Count consecutive failures (reset upon first non-dead status)
+            if status == &Status::Dead {
+                replica.failures += 1;
+            } else {
+                replica.failures = 0;
+            }
+
+            replica.status = Some(*status);
+            replica.latency = latency.map(|latency| latency.as_millis() as u64);
+            replica.exit_code = exit_code;
+            replica.last_check = Some(now());
+            replica.reported = Some(reported);
+        }
+    }
+}
+
+pub fn record_icmp(service_id: &str, node_id: &str, replica_id: &str, stats: &ICMPStats) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            let replica = node.replicas.entry(replica_id.to_owned()).or_default();
+
+            replica.icmp = Some(StatesServiceNodeReplicaICMP {
+                sent: stats.sent,
+                received: stats.received,
+                loss: stats.loss_percent(),
+                rtt_average: stats.rtt_average.map(|rtt| rtt.as_millis() as u64),
+                rtt_maximum: stats.rtt_maximum.map(|rtt| rtt.as_millis() as u64),
+                jitter: stats.jitter.map(|jitter| jitter.as_millis() as u64),
+            });
+        }
+    }
+}
+
+pub fn record_addresses(service_id: &str, node_id: &str, replica_id: &str, failed: &[IpAddr]) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            let replica = node.replicas.entry(replica_id.to_owned()).or_default();
+
+            replica.failed_addresses =
+                Some(failed.iter().map(|address| address.to_string()).collect());
+        }
+    }
+}
+
+pub fn record_report(
+    service_id: &str,
+    node_id: &str,
+    replica_id: &str,
+    report: Option<ScriptReport>,
+) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            let replica = node.replicas.entry(replica_id.to_owned()).or_default();
+
+            match report {
+                Some(report) => {
+                    replica.message = report.message;
+                    replica.perfdata =
+                        Some(report.perfdata).filter(|perfdata| !perfdata.is_empty());
+                }
+                None => {
+                    replica.message = None;
+                    replica.perfdata = None;
+                }
+            }
+        }
+    }
+}
+
+pub fn count_report(service_id: &str, node_id: &str, replica_id: &str, is_success: bool) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            let replica = node.replicas.entry(replica_id.to_owned()).or_default();
+
+            replica.report_attempts += 1;
+
+            if !is_success {
+                replica.report_failures += 1;
+            }
+        }
+    }
+}
+
+pub fn count_report_target(
+    service_id: &str,
+    node_id: &str,
+    replica_id: &str,
+    target: &str,
+    is_success: bool,
+) {
+    if let Ok(mut states) = PROBE_STATES.write() {
+        if let Some(node) = states
+            .services
+            .get_mut(service_id)
+            .and_then(|service| service.nodes.get_mut(node_id))
+        {
+            let report_target = node
+                .replicas
+                .entry(replica_id.to_owned())
+                .or_default()
+                .report_targets
+                .entry(target.to_owned())
+                .or_default();
+
+            report_target.reported = Some(is_success);
+            report_target.attempts += 1;
+
+            if !is_success {
+                report_target.failures += 1;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
@@ -4,7 +4,8 @@
 // Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
 pub enum Status {
     Healthy,
     Sick,
@@ -13,10 +14,26 @@ pub enum Status {
 
 impl Status {
     pub fn as_str(&self) -> &'static str {
-        match self {
-            &Status::Healthy => "healthy",
-            &Status::Sick => "sick",
-            &Status::Dead => "dead",
+        match *self {
+            Status::Healthy => "healthy",
+            Status::Sick => "sick",
+            Status::Dead => "dead",
+        }
+    }
+
+    pub fn as_value(&self) -> u8 {
+        match *self {
+            Status::Healthy => 0,
+            Status::Sick => 1,
+            Status::Dead => 2,
+        }
+    }
+
+    pub fn worst(self, other: Status) -> Status {
+        match (self, other) {
+            (Status::Dead, _) | (_, Status::Dead) => Status::Dead,
+            (Status::Sick, _) | (_, Status::Sick) => Status::Sick,
+            _ => Status::Healthy,
         }
     }
 }
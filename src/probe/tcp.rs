@@ -0,0 +1,172 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::io::{ErrorKind, Read, Write};
+
+use super::status::Status;
+use crate::config::config::ConfigProbeServiceNode;
+
+const TCP_BANNER_MAXIMUM_SIZE: usize = 4096;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum TCPProtocol {
+    SMTP,
+    SSH,
+    FTP,
+    Redis,
+    IMAP,
+    POP3,
+}
+
+impl TCPProtocol {
+    pub fn from_scheme(scheme: &str) -> Option<TCPProtocol> {
+        match scheme {
+            "smtp" => Some(Self::SMTP),
+            "ssh" => Some(Self::SSH),
+            "ftp" => Some(Self::FTP),
+            "redis" => Some(Self::Redis),
+            "imap" => Some(Self::IMAP),
+            "pop3" => Some(Self::POP3),
+            _ => None,
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Self::SMTP => 25,
+            Self::SSH => 22,
+            Self::FTP => 21,
+            Self::Redis => 6379,
+            Self::IMAP => 143,
+            Self::POP3 => 110,
+        }
+    }
+
+    fn payload(&self) -> Option<&'static str> {
+        match self {
+            Self::Redis => Some("PING\r\n"),
+            _ => None,
+        }
+    }
+
+    fn banner_prefix(&self) -> &'static str {
+        match self {
+            Self::SMTP => "220",
+            Self::SSH => "SSH-",
+            Self::FTP => "220",
+            Self::Redis => "+PONG",
+            Self::IMAP => "* OK",
+            Self::POP3 => "+OK",
+        }
+    }
+}
+
+pub fn has_exchange(node: &ConfigProbeServiceNode, protocol: Option<TCPProtocol>) -> bool {
+    protocol.is_some()
+        || node.tcp_payload.is_some()
+        || node.tcp_banner_prefix.is_some()
+        || node.tcp_banner_match.is_some()
+}
+
+pub fn exchange<S: Read + Write>(
+    node: &ConfigProbeServiceNode,
+    protocol: Option<TCPProtocol>,
+    stream: &mut S,
+) -> Result<Vec<u8>, ()> {
+    // Send payload (if any; node payload overrides the protocol payload)
+    let payload = node
+        .tcp_payload
+        .as_deref()
+        .or(protocol.and_then(|protocol| protocol.payload()));
+
+    if let Some(payload) = payload {
+        stream
+            .write_all(payload.as_bytes())
+            .and_then(|_| stream.flush())
+            .map_err(|err| debug!("prober poll could not send tcp payload: {}", err))?;
+    }
+
+    // Nothing to expect? (do not wait for a banner)
+    if !has_banner_matchers(node, protocol) {
+        return Ok(Vec::new());
+    }
+
+    // Read banner, up to the first line feed
+    let mut banner = Vec::new();
+    let mut buffer = [0; 512];
+
+    while !banner.contains(&b'\n') && banner.len() < TCP_BANNER_MAXIMUM_SIZE {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read_size) => banner.extend_from_slice(&buffer[..read_size]),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => {
+                // Notice: a partial banner can still be evaluated, if some bytes were read.
+                if banner.is_empty() {
+                    debug!("prober poll could not read tcp banner: {}", err);
+
+                    return Err(());
+                }
+
+                break;
+            }
+        }
+    }
+
+    if banner.is_empty() {
+        debug!("prober poll got no tcp banner (connection closed)");
+
+        return Err(());
+    }
+
+    Ok(banner)
+}
+
+pub fn evaluate(
+    node: &ConfigProbeServiceNode,
+    protocol: Option<TCPProtocol>,
+    banner: &[u8],
+) -> Status {
+    let banner = String::from_utf8_lossy(banner);
+
+    // Check banner prefix (node prefix overrides the protocol prefix)
+    let banner_prefix = node
+        .tcp_banner_prefix
+        .as_deref()
+        .or(protocol.map(|protocol| protocol.banner_prefix()));
+
+    if let Some(banner_prefix) = banner_prefix {
+        if !banner.starts_with(banner_prefix) {
+            debug!(
+                "prober poll tcp banner does not start with: {} (got: {})",
+                banner_prefix,
+                banner.trim_end()
+            );
+
+            return Status::Sick;
+        }
+    }
+
+    // Check banner pattern
+    if let Some(ref banner_match) = node.tcp_banner_match {
+        if !banner_match.is_match(&banner) {
+            debug!(
+                "prober poll tcp banner does not match: {} (got: {})",
+                banner_match,
+                banner.trim_end()
+            );
+
+            return Status::Sick;
+        }
+    }
+
+    Status::Healthy
+}
+
+fn has_banner_matchers(node: &ConfigProbeServiceNode, protocol: Option<TCPProtocol>) -> bool {
+    protocol.is_some() || node.tcp_banner_prefix.is_some() || node.tcp_banner_match.is_some()
+}
@@ -0,0 +1,346 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme, StreamOwned,
+};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use super::status::Status;
+use crate::config::config::{ConfigProbe, ConfigProbeServiceNode};
+use crate::APP_CONF;
+
+const SECONDS_IN_DAY: i64 = 86400;
+
+pub type TLSStream = StreamOwned<ClientConnection, TcpStream>;
+
+lazy_static! {
+    static ref TLS_CLIENT_CONFIGS: RwLock<HashMap<TLSOptions, Arc<ClientConfig>>> =
+        RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TLSOptions {
+    ca_file: Option<PathBuf>,
+    cert_file: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+    verify: bool,
+    alpn: Option<&'static str>,
+}
+
+#[derive(Debug)]
+struct TLSNoVerification(Arc<CryptoProvider>);
+
+impl TLSOptions {
+    pub fn from_config(probe: &ConfigProbe, node: &ConfigProbeServiceNode) -> TLSOptions {
+        // Notice: node-level options override the global probe options.
+        TLSOptions {
+            ca_file: node.tls_ca_file.clone().or(probe.tls_ca_file.clone()),
+            cert_file: node.tls_cert_file.clone().or(probe.tls_cert_file.clone()),
+            key_file: node.tls_key_file.clone().or(probe.tls_key_file.clone()),
+            verify: node.tls_verify.unwrap_or(probe.tls_verify),
+            alpn: None,
+        }
+    }
+
+    pub fn with_alpn(mut self, alpn: &'static str) -> TLSOptions {
+        self.alpn = Some(alpn);
+
+        self
+    }
+}
+
+impl ServerCertVerifier for TLSNoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+pub fn handshake(
+    node: &ConfigProbeServiceNode,
+    host: &str,
+    stream: TcpStream,
+) -> Result<(TLSStream, Status), ()> {
+    handshake_with_options(
+        node,
+        &TLSOptions::from_config(&APP_CONF.probe, node),
+        host,
+        stream,
+    )
+}
+
+pub fn handshake_alpn(
+    node: &ConfigProbeServiceNode,
+    host: &str,
+    stream: TcpStream,
+    alpn: &'static str,
+) -> Result<(TLSStream, Status), ()> {
+    handshake_with_options(
+        node,
+        &TLSOptions::from_config(&APP_CONF.probe, node).with_alpn(alpn),
+        host,
+        stream,
+    )
+}
+
+fn handshake_with_options(
+    node: &ConfigProbeServiceNode,
+    options: &TLSOptions,
+    host: &str,
+    mut stream: TcpStream,
+) -> Result<(TLSStream, Status), ()> {
+    debug!("prober poll will perform tls handshake with host: {}", host);
+
+    let server_name = ServerName::try_from(host.to_owned()).map_err(|_| {
+        debug!("prober poll tls host is not a valid server name: {}", host);
+    })?;
+
+    let client_config = acquire_client_config(options)?;
+
+    let mut connection = ClientConnection::new(client_config, server_name).map_err(|err| {
+        error!("prober poll could not create tls connection: {}", err);
+    })?;
+
+    // Complete TLS handshake (this validates the certificate chain and the hostname)
+    while connection.is_handshaking() {
+        if let Err(err) = connection.complete_io(&mut stream) {
+            debug!(
+                "prober poll tls handshake failed with host: {} ({}, error: {})",
+                host,
+                describe_error(&err),
+                err
+            );
+
+            return Err(());
+        }
+    }
+
+    // Inspect peer certificate (this checks for the certificate expiry date)
+    let status = inspect(node, host, &connection);
+
+    if status == Status::Dead {
+        return Err(());
+    }
+
+    Ok((StreamOwned::new(connection, stream), status))
+}
+
+fn inspect(node: &ConfigProbeServiceNode, host: &str, connection: &ClientConnection) -> Status {
+    // Notice: the first certificate in the peer chain is the leaf certificate.
+    let leaf_certificate = connection
+        .peer_certificates()
+        .and_then(|certificates| certificates.first());
+
+    let not_after = match leaf_certificate
+        .and_then(|certificate| X509Certificate::from_der(certificate.as_ref()).ok())
+    {
+        Some((_, certificate)) => certificate.validity().not_after.timestamp(),
+        None => {
+            debug!(
+                "prober poll got no valid tls certificate from host: {}",
+                host
+            );
+
+            return Status::Dead;
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+
+    let expires_in_days = (not_after - now) / SECONDS_IN_DAY;
+
+    if not_after <= now {
+        debug!(
+            "prober poll got expired tls certificate from host: {}",
+            host
+        );
+
+        Status::Dead
+    } else if expires_in_days < node.tls_expiry_warn_days as i64 {
+        warn!(
+            "prober poll got tls certificate expiring in {} days from host: {}",
+            expires_in_days, host
+        );
+
+        Status::Sick
+    } else {
+        debug!(
+            "prober poll got tls certificate expiring in {} days from host: {}",
+            expires_in_days, host
+        );
+
+        Status::Healthy
+    }
+}
+
+fn describe_error(err: &io::Error) -> &'static str {
+    // Acquire the underlying TLS error, if any (IO errors wrap TLS errors in 'rustls')
+    let tls_error = err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+
+    match tls_error {
+        Some(rustls::Error::InvalidCertificate(certificate_error)) => match certificate_error {
+            CertificateError::Expired | CertificateError::ExpiredContext { .. } => {
+                "certificate expired"
+            }
+            CertificateError::NotValidYet | CertificateError::NotValidYetContext { .. } => {
+                "certificate not valid yet"
+            }
+            CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. } => {
+                "hostname mismatch"
+            }
+            CertificateError::UnknownIssuer => "untrusted chain",
+            _ => "invalid certificate",
+        },
+        Some(_) => "protocol error",
+        None => "transport error",
+    }
+}
+
+pub fn make_client_config(options: &TLSOptions) -> Result<ClientConfig, String> {
+    let builder = ClientConfig::builder();
+
+    // Build certificate verifier (either disabled, or built-in roots plus any custom CA bundle)
+    let builder = if options.verify {
+        let mut root_store = RootCertStore::empty();
+
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        if let Some(ref ca_file) = options.ca_file {
+            let ca_certificates = read_certificates(ca_file)?;
+
+            if ca_certificates.is_empty() {
+                return Err(format!("no certificate found in: {}", ca_file.display()));
+            }
+
+            for ca_certificate in ca_certificates {
+                root_store.add(ca_certificate).map_err(|err| {
+                    format!("invalid certificate in: {} ({})", ca_file.display(), err)
+                })?;
+            }
+        }
+
+        builder.with_root_certificates(root_store)
+    } else {
+        let provider = CryptoProvider::get_default()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()));
+
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(TLSNoVerification(provider)))
+    };
+
+    // Attach client certificate (if any, for mutual TLS)
+    let mut client_config = match (&options.cert_file, &options.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let certificates = read_certificates(cert_file)?;
+
+            if certificates.is_empty() {
+                return Err(format!("no certificate found in: {}", cert_file.display()));
+            }
+
+            let key = PrivateKeyDer::from_pem_file(key_file)
+                .map_err(|err| format!("cannot read key file: {} ({})", key_file.display(), err))?;
+
+            builder
+                .with_client_auth_cert(certificates, key)
+                .map_err(|err| format!("invalid client certificate or key ({})", err))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err("both a client certificate and key must be set".to_string()),
+    };
+
+    // Advertise application protocol (if any, eg. HTTP/2 for gRPC)
+    if let Some(alpn) = options.alpn {
+        client_config.alpn_protocols = vec![alpn.as_bytes().to_vec()];
+    }
+
+    Ok(client_config)
+}
+
+fn acquire_client_config(options: &TLSOptions) -> Result<Arc<ClientConfig>, ()> {
+    // Acquire cached client configuration? (building one is expensive)
+    if let Ok(client_configs) = TLS_CLIENT_CONFIGS.read() {
+        if let Some(client_config) = client_configs.get(options) {
+            return Ok(client_config.clone());
+        }
+    }
+
+    // Notice: all TLS options have been validated upon configuration load, thus this should \
+    //   not fail, unless files were altered in the meantime.
+    let client_config = Arc::new(make_client_config(options).map_err(|err| {
+        error!("prober poll could not build tls configuration: {}", err);
+    })?);
+
+    if let Ok(mut client_configs) = TLS_CLIENT_CONFIGS.write() {
+        client_configs.insert(options.clone(), client_config.clone());
+    }
+
+    Ok(client_config)
+}
+
+fn read_certificates(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>, String> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("cannot read certificate file: {} ({})", path.display(), err))
+}
@@ -0,0 +1,110 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::fmt;
+
+use serde::de::{Error, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use super::status::Status;
+use crate::config::config::ConfigProbeServiceNode;
+
+#[derive(Debug, Clone)]
+pub struct UDPHexBytes(Vec<u8>);
+
+impl UDPHexBytes {
+    pub fn parse_from(raw_hex: &str) -> Result<UDPHexBytes, ()> {
+        // Notice: whitespaces are ignored, so that long payloads can be split in readable groups \
+        //   (eg. 'ff ff ff ff 54 53 6f 75').
+        let digits: Vec<u8> = raw_hex
+            .bytes()
+            .filter(|digit| !digit.is_ascii_whitespace())
+            .collect();
+
+        let pairs = digits.chunks_exact(2);
+
+        if !pairs.remainder().is_empty() {
+            return Err(());
+        }
+
+        pairs
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or(())
+            })
+            .collect::<Result<Vec<u8>, ()>>()
+            .map(UDPHexBytes)
+    }
+}
+
+impl<'de> Deserialize<'de> for UDPHexBytes {
+    fn deserialize<D>(de: D) -> Result<UDPHexBytes, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct UDPHexBytesVisitor;
+
+        impl<'de> Visitor<'de> for UDPHexBytesVisitor {
+            type Value = UDPHexBytes;
+
+            fn expecting(&self, format: &mut fmt::Formatter) -> fmt::Result {
+                format.write_str("an hexadecimal string")
+            }
+
+            fn visit_str<E: Error>(self, value: &str) -> Result<UDPHexBytes, E> {
+                UDPHexBytes::parse_from(value).map_err(|_| E::custom("invalid"))
+            }
+        }
+
+        de.deserialize_str(UDPHexBytesVisitor)
+    }
+}
+
+pub fn acquire_payload(node: &ConfigProbeServiceNode) -> &[u8] {
+    match (&node.udp_payload, &node.udp_payload_hex) {
+        (Some(payload), _) => payload.as_bytes(),
+        (None, Some(payload_hex)) => &payload_hex.0,
+        (None, None) => &[],
+    }
+}
+
+pub fn evaluate(node: &ConfigProbeServiceNode, response: &[u8]) -> Status {
+    let response_prefix = match (&node.udp_response_prefix, &node.udp_response_prefix_hex) {
+        (Some(prefix), _) => Some(prefix.as_bytes()),
+        (None, Some(prefix_hex)) => Some(prefix_hex.0.as_slice()),
+        (None, None) => None,
+    };
+
+    // Check response prefix (a replica responding unexpected data is considered as sick)
+    if let Some(response_prefix) = response_prefix {
+        if !response.starts_with(response_prefix) {
+            debug!(
+                "prober poll udp response does not start with expected prefix (got {} bytes)",
+                response.len()
+            );
+
+            return Status::Sick;
+        }
+    }
+
+    Status::Healthy
+}
+
+pub fn validate(node: &ConfigProbeServiceNode) -> Result<(), String> {
+    if node.udp_payload.is_some() && node.udp_payload_hex.is_some() {
+        return Err("only one of udp_payload and udp_payload_hex can be set".to_string());
+    }
+
+    if node.udp_response_prefix.is_some() && node.udp_response_prefix_hex.is_some() {
+        return Err(
+            "only one of udp_response_prefix and udp_response_prefix_hex can be set".to_string(),
+        );
+    }
+
+    Ok(())
+}
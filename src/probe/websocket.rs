@@ -0,0 +1,542 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use base64::engine::general_purpose::STANDARD as base64_encoder;
+use base64::Engine;
+use http_req::response::Response;
+use sha1::{Digest, Sha1};
+use url::{Position, Url};
+
+use super::http::{acquire_headers as http_acquire_headers, POLL_HTTP_HEADER_USERAGENT};
+use super::replica::ReplicaURL;
+use super::status::Status;
+use crate::config::config::ConfigProbeServiceNode;
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const WEBSOCKET_VERSION: &str = "13";
+const WEBSOCKET_STATUS_SWITCHING_PROTOCOLS: u16 = 101;
+
+const WEBSOCKET_RESPONSE_HEAD_MAXIMUM_SIZE: u64 = 65536;
+const WEBSOCKET_MESSAGE_MAXIMUM_SIZE: u64 = 1048576;
+
+const WEBSOCKET_OPCODE_CONTINUATION: u8 = 0x0;
+const WEBSOCKET_OPCODE_TEXT: u8 = 0x1;
+const WEBSOCKET_OPCODE_BINARY: u8 = 0x2;
+const WEBSOCKET_OPCODE_CLOSE: u8 = 0x8;
+const WEBSOCKET_OPCODE_PING: u8 = 0x9;
+const WEBSOCKET_OPCODE_PONG: u8 = 0xa;
+
+const WEBSOCKET_CLOSE_NORMAL: u16 = 1000;
+
+pub struct WebSocketResponse {
+    pub status_code: u16,
+    pub message: Option<Vec<u8>>,
+}
+
+struct WebSocketFrame {
+    is_final: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+pub fn request<S: Read + Write>(
+    node: &ConfigProbeServiceNode,
+    url: &str,
+    stream: &mut S,
+) -> Result<WebSocketResponse, ()> {
+    let url_parsed = Url::parse(url).map_err(|_| {
+        error!(
+            "prober poll websocket url is invalid: {}",
+            ReplicaURL::redact(url)
+        );
+    })?;
+
+    // Acquire request host and path (the port is omitted from the host if it is the default one)
+    let host = match (url_parsed.host_str(), url_parsed.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_owned(),
+        _ => return Err(()),
+    };
+
+    let path = match &url_parsed[Position::BeforePath..Position::AfterQuery] {
+        "" => "/",
+        path => path,
+    };
+
+    // Generate handshake key (a random nonce, which the server must hash in its response)
+    let mut key_bytes = [0; 16];
+
+    getrandom::getrandom(&mut key_bytes).map_err(|err| {
+        error!("prober poll could not generate websocket key: {}", err);
+    })?;
+
+    let key = base64_encoder.encode(key_bytes);
+
+    // Build upgrade request message
+    let mut request_message = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nUpgrade: websocket\r\n\
+            Connection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: {}\r\n",
+        path, host, *POLL_HTTP_HEADER_USERAGENT, key, WEBSOCKET_VERSION
+    );
+
+    for (header_name, header_value) in http_acquire_headers(node) {
+        request_message.push_str(&format!("{}: {}\r\n", header_name, header_value));
+    }
+
+    request_message.push_str("\r\n");
+
+    debug!("prober poll will send websocket upgrade request");
+
+    stream
+        .write_all(request_message.as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(|err| {
+            debug!(
+                "prober poll could not send websocket upgrade request (error: {})",
+                err
+            );
+        })?;
+
+    // Receive response head
+    // Notice: the server may send frames right after its response head, therefore the same \
+    //   buffered reader must be used to read frames afterwards.
+    let mut reader = BufReader::new(stream);
+    let mut response_head = Vec::new();
+
+    loop {
+        let mut line = Vec::new();
+
+        match (&mut reader)
+            .take(WEBSOCKET_RESPONSE_HEAD_MAXIMUM_SIZE)
+            .read_until(b'\n', &mut line)
+        {
+            Ok(0) | Err(_) => {
+                debug!("prober poll did not receive a full websocket upgrade response head");
+
+                return Err(());
+            }
+            Ok(_) => {
+                response_head.extend(&line);
+
+                if line == b"\r\n" || line == b"\n" {
+                    break;
+                }
+            }
+        }
+    }
+
+    let response = Response::from_head(&response_head).map_err(|err| {
+        debug!(
+            "prober poll received an invalid websocket upgrade response (error: {})",
+            err
+        );
+    })?;
+
+    let status_code = u16::from(response.status_code());
+
+    if status_code != WEBSOCKET_STATUS_SWITCHING_PROTOCOLS {
+        return Ok(WebSocketResponse {
+            status_code,
+            message: None,
+        });
+    }
+
+    // Check that the server actually upgraded the connection to a WebSocket
+    let is_upgraded = response
+        .headers()
+        .get("Upgrade")
+        .map(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+
+    let is_accepted = response
+        .headers()
+        .get("Sec-WebSocket-Accept")
+        .map(|accept| accept.trim() == acquire_accept(&key))
+        .unwrap_or(false);
+
+    if !is_upgraded || !is_accepted {
+        debug!("prober poll got an invalid websocket upgrade response (bad upgrade or accept)");
+
+        return Err(());
+    }
+
+    // Exchange a message (if any is configured, or if a message is expected)
+    let message = if has_exchange(node) {
+        if let Some(ref websocket_message) = node.websocket_message {
+            write_frame(
+                reader.get_mut(),
+                WEBSOCKET_OPCODE_TEXT,
+                websocket_message.as_bytes(),
+            )
+            .map_err(|err| {
+                debug!("prober poll could not send websocket message: {}", err);
+            })?;
+        }
+
+        Some(read_message(&mut reader)?)
+    } else {
+        None
+    };
+
+    // Close connection (the close handshake is not awaited)
+    let _ = write_frame(
+        reader.get_mut(),
+        WEBSOCKET_OPCODE_CLOSE,
+        &WEBSOCKET_CLOSE_NORMAL.to_be_bytes(),
+    );
+
+    Ok(WebSocketResponse {
+        status_code,
+        message,
+    })
+}
+
+pub fn evaluate(node: &ConfigProbeServiceNode, response: &WebSocketResponse) -> Status {
+    // Check upgrade (a refused upgrade is considered as down)
+    if response.status_code != WEBSOCKET_STATUS_SWITCHING_PROTOCOLS {
+        debug!(
+            "prober poll got non-upgraded websocket status code: {}",
+            response.status_code
+        );
+
+        return Status::Dead;
+    }
+
+    // Check reply message
+    if let (Some(ref response_match), Some(ref message)) =
+        (&node.websocket_response_match, &response.message)
+    {
+        let message = String::from_utf8_lossy(message);
+
+        if !response_match.is_match(&message) {
+            debug!(
+                "prober poll websocket reply does not match: {} (got: {})",
+                response_match,
+                message.trim_end()
+            );
+
+            return Status::Sick;
+        }
+    }
+
+    Status::Healthy
+}
+
+fn has_exchange(node: &ConfigProbeServiceNode) -> bool {
+    node.websocket_message.is_some() || node.websocket_response_match.is_some()
+}
+
+fn acquire_accept(key: &str) -> String {
+    let mut hasher = Sha1::new();
+
+    hasher.update(key.as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+
+    base64_encoder.encode(hasher.finalize())
+}
+
+fn read_message<S: Read + Write>(reader: &mut BufReader<&mut S>) -> Result<Vec<u8>, ()> {
+    let mut message = Vec::new();
+
+    // Read frames until a full data message is received (answering pings meanwhile)
+    loop {
+        let frame = read_frame(reader).map_err(|err| {
+            debug!("prober poll could not read websocket frame: {}", err);
+        })?;
+
+        match frame.opcode {
+            WEBSOCKET_OPCODE_TEXT | WEBSOCKET_OPCODE_BINARY | WEBSOCKET_OPCODE_CONTINUATION => {
+                message.extend(frame.payload);
+
+                if message.len() as u64 > WEBSOCKET_MESSAGE_MAXIMUM_SIZE {
+                    debug!("prober poll got a too large websocket message");
+
+                    return Err(());
+                }
+
+                if frame.is_final {
+                    return Ok(message);
+                }
+            }
+            WEBSOCKET_OPCODE_PING => {
+                write_frame(reader.get_mut(), WEBSOCKET_OPCODE_PONG, &frame.payload)
+                    .map_err(|err| debug!("prober poll could not send websocket pong: {}", err))?;
+            }
+            WEBSOCKET_OPCODE_PONG => {}
+            WEBSOCKET_OPCODE_CLOSE => {
+                debug!("prober poll got websocket closed before receiving a message");
+
+                return Err(());
+            }
+            opcode => {
+                debug!("prober poll got unknown websocket opcode: {}", opcode);
+
+                return Err(());
+            }
+        }
+    }
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<WebSocketFrame> {
+    let mut header = [0; 2];
+
+    reader.read_exact(&mut header)?;
+
+    // Acquire payload length (extended lengths are stored in the following bytes)
+    let length = match header[1] & 0x7f {
+        126 => {
+            let mut length = [0; 2];
+
+            reader.read_exact(&mut length)?;
+
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0; 8];
+
+            reader.read_exact(&mut length)?;
+
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+
+    if length > WEBSOCKET_MESSAGE_MAXIMUM_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame is too large",
+        ));
+    }
+
+    // Notice: servers must not mask frames, though masked frames are still unmasked here.
+    let mut mask = None;
+
+    if header[1] & 0x80 != 0 {
+        let mut mask_key = [0; 4];
+
+        reader.read_exact(&mut mask_key)?;
+
+        mask = Some(mask_key);
+    }
+
+    let mut payload = vec![0; length as usize];
+
+    reader.read_exact(&mut payload)?;
+
+    if let Some(mask_key) = mask {
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask_key[index % 4];
+        }
+    }
+
+    Ok(WebSocketFrame {
+        is_final: header[0] & 0x80 != 0,
+        opcode: header[0] & 0x0f,
+        payload,
+    })
+}
+
+fn write_frame<W: Write>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    // Notice: clients must mask all frames they send, with a random mask key.
+    let mut mask_key = [0; 4];
+
+    getrandom::getrandom(&mut mask_key).map_err(io::Error::other)?;
+
+    let mut frame = vec![0x80 | opcode];
+
+    match payload.len() {
+        length if length < 126 => frame.push(0x80 | length as u8),
+        length if length <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    frame.extend_from_slice(&mask_key);
+
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| byte ^ mask_key[index % 4]),
+    );
+
+    writer.write_all(&frame).and_then(|_| writer.flush())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: Vec<u8>) -> MockStream {
+            MockStream {
+                input: Cursor::new(input),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn server_frame(is_final: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if is_final { 0x80 } else { 0 } | opcode];
+
+        match payload.len() {
+            length if length < 126 => frame.push(length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+
+    fn read_stream_message(input: Vec<u8>) -> (Result<Vec<u8>, ()>, Vec<u8>) {
+        let mut stream = MockStream::new(input);
+        let result = read_message(&mut BufReader::new(&mut stream));
+
+        (result, stream.output)
+    }
+
+    #[test]
+    fn it_round_trips_masked_frames() {
+        for (size, length_marker) in [(5, 5), (200, 126), (70000, 127)] {
+            let payload: Vec<u8> = (0..size).map(|index| index as u8).collect();
+            let mut buffer = Vec::new();
+
+            write_frame(&mut buffer, WEBSOCKET_OPCODE_BINARY, &payload).unwrap();
+
+            assert_eq!(buffer[0], 0x80 | WEBSOCKET_OPCODE_BINARY);
+            assert_eq!(buffer[1], 0x80 | length_marker);
+
+            let frame = read_frame(&mut Cursor::new(buffer)).unwrap();
+
+            assert!(frame.is_final);
+            assert_eq!(frame.opcode, WEBSOCKET_OPCODE_BINARY);
+            assert_eq!(frame.payload, payload);
+        }
+    }
+
+    #[test]
+    fn it_reads_extended_lengths() {
+        for size in [125, 126, 65535, 65536] {
+            let payload = vec![b'a'; size];
+            let frame = read_frame(&mut Cursor::new(server_frame(
+                true,
+                WEBSOCKET_OPCODE_TEXT,
+                &payload,
+            )))
+            .unwrap();
+
+            assert_eq!(frame.payload.len(), size);
+        }
+    }
+
+    #[test]
+    fn it_rejects_too_large_frames() {
+        let mut header = vec![0x80 | WEBSOCKET_OPCODE_BINARY, 127];
+
+        header.extend_from_slice(&(WEBSOCKET_MESSAGE_MAXIMUM_SIZE + 1).to_be_bytes());
+
+        let error = read_frame(&mut Cursor::new(header)).err().unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_rejects_too_large_messages() {
+        let chunk = vec![b'a'; WEBSOCKET_MESSAGE_MAXIMUM_SIZE as usize / 2 + 1];
+        let mut input = server_frame(false, WEBSOCKET_OPCODE_BINARY, &chunk);
+
+        input.extend(server_frame(true, WEBSOCKET_OPCODE_CONTINUATION, &chunk));
+
+        assert!(read_stream_message(input).0.is_err());
+    }
+
+    #[test]
+    fn it_reassembles_continuations() {
+        let mut input = server_frame(false, WEBSOCKET_OPCODE_TEXT, b"hel");
+
+        input.extend(server_frame(false, WEBSOCKET_OPCODE_CONTINUATION, b"l"));
+        input.extend(server_frame(true, WEBSOCKET_OPCODE_CONTINUATION, b"o"));
+
+        assert_eq!(read_stream_message(input).0, Ok(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn it_replies_to_pings_while_reading() {
+        let mut input = server_frame(false, WEBSOCKET_OPCODE_TEXT, b"hel");
+
+        input.extend(server_frame(true, WEBSOCKET_OPCODE_PING, b"beat"));
+        input.extend(server_frame(true, WEBSOCKET_OPCODE_PONG, b""));
+        input.extend(server_frame(true, WEBSOCKET_OPCODE_CONTINUATION, b"lo"));
+
+        let (result, output) = read_stream_message(input);
+
+        assert_eq!(result, Ok(b"hello".to_vec()));
+
+        // Pong must echo the ping payload, and be masked
+        assert_ne!(output[1] & 0x80, 0);
+
+        let pong = read_frame(&mut Cursor::new(output)).unwrap();
+
+        assert_eq!(pong.opcode, WEBSOCKET_OPCODE_PONG);
+        assert_eq!(pong.payload, b"beat");
+    }
+
+    #[test]
+    fn it_fails_upon_close_or_unknown_opcode() {
+        let close = server_frame(true, WEBSOCKET_OPCODE_CLOSE, &1000u16.to_be_bytes());
+
+        assert!(read_stream_message(close).0.is_err());
+        assert!(read_stream_message(server_frame(true, 0x3, b"")).0.is_err());
+        assert!(read_stream_message(Vec::new()).0.is_err());
+    }
+
+    #[test]
+    fn it_computes_accept_key() {
+        // Example from RFC 6455
+        assert_eq!(
+            acquire_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+}
@@ -0,0 +1,89 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::net::SocketAddr;
+
+use serde_json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use super::metrics::render as metrics_render;
+use crate::probe::states::PROBE_STATES;
+
+pub fn run(inet: SocketAddr) {
+    let server = match Server::http(inet) {
+        Ok(server) => server,
+        Err(err) => {
+            error!("could not bind responder on: {} (error: {})", inet, err);
+
+            return;
+        }
+    };
+
+    info!("responder listening on: {}", inet);
+
+    for request in server.incoming_requests() {
+        debug!(
+            "responder got request: {} {}",
+            request.method(),
+            request.url()
+        );
+
+        handle(request);
+    }
+}
+
+fn handle(request: Request) {
+    let response = match (request.method(), request.url()) {
+        (&Method::Get, "/") | (&Method::Get, "/status") => respond_status(),
+        (&Method::Get, "/health") => respond_health(),
+        (&Method::Get, "/metrics") => respond_metrics(),
+        _ => Response::from_string("Not Found").with_status_code(404),
+    };
+
+    if let Err(err) = request.respond(response) {
+        debug!("responder could not send response (error: {})", err);
+    }
+}
+
+fn respond_status() -> Response<std::io::Cursor<Vec<u8>>> {
+    match PROBE_STATES.read() {
+        Ok(states) => {
+            // Notice: fail hard if states are invalid (they should never be)
+            let states_json = serde_json::to_vec(&*states).expect("invalid probe states");
+
+            Response::from_data(states_json).with_header(
+                "Content-Type: application/json"
+                    .parse::<Header>()
+                    .expect("invalid content type header"),
+            )
+        }
+        Err(_) => Response::from_string("Internal Server Error").with_status_code(500),
+    }
+}
+
+fn respond_health() -> Response<std::io::Cursor<Vec<u8>>> {
+    let is_stuck = PROBE_STATES
+        .read()
+        .map(|states| states.is_stuck())
+        .unwrap_or(true);
+
+    if is_stuck {
+        Response::from_string("Stuck").with_status_code(503)
+    } else {
+        Response::from_string("OK").with_status_code(200)
+    }
+}
+
+fn respond_metrics() -> Response<std::io::Cursor<Vec<u8>>> {
+    match PROBE_STATES.read() {
+        Ok(states) => Response::from_string(metrics_render(&states)).with_header(
+            "Content-Type: text/plain; version=0.0.4"
+                .parse::<Header>()
+                .expect("invalid content type header"),
+        ),
+        Err(_) => Response::from_string("Internal Server Error").with_status_code(500),
+    }
+}
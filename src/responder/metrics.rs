@@ -0,0 +1,305 @@
+// Vigil Local
+//
+// Vigil local probe relay
+// Copyright: 2020, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::fmt::Write;
+
+use crate::probe::states::{
+    States, StatesServiceNode, StatesServiceNodeReplica, StatesServiceNodeReplicaReportTarget,
+};
+
+const METRICS_PREFIX: &str = "vigil_local";
+
+pub fn render(states: &States) -> String {
+    let mut output = String::new();
+
+    // Render node metrics
+    render_node(
+        &mut output,
+        states,
+        "node_interval_seconds",
+        "gauge",
+        "Configured probe interval of the node",
+        |node| Some(node.interval.to_string()),
+    );
+    render_node(
+        &mut output,
+        states,
+        "node_last_run_timestamp_seconds",
+        "gauge",
+        "Time at which the last probe run of the node completed",
+        |node| node.last_run.map(|last_run| last_run.to_string()),
+    );
+    render_node(
+        &mut output,
+        states,
+        "node_run_duration_seconds",
+        "gauge",
+        "Duration of the last probe run of the node",
+        |node| node.last_run_duration.map(milliseconds_to_seconds),
+    );
+
+    // Render replica metrics
+    render_replica(
+        &mut output,
+        states,
+        "replica_status",
+        "gauge",
+        "Last probed status of the replica (0 = healthy, 1 = sick, 2 = dead)",
+        |replica| replica.status.map(|status| status.as_value().to_string()),
+    );
+    render_replica(
+        &mut output,
+        states,
+        "replica_latency_seconds",
+        "gauge",
+        "Last probed latency of the replica",
+        |replica| replica.latency.map(milliseconds_to_seconds),
+    );
+    render_replica(
+        &mut output,
+        states,
+        "replica_failures",
+        "gauge",
+        "Consecutive dead probes of the replica",
+        |replica| replica.status.map(|_| replica.failures.to_string()),
+    );
+    render_replica(
+        &mut output,
+        states,
+        "replica_last_check_timestamp_seconds",
+        "gauge",
+        "Time at which the replica was last probed",
+        |replica| replica.last_check.map(|last_check| last_check.to_string()),
+    );
+    render_replica(
+        &mut output,
+        states,
+        "replica_failed_addresses",
+        "gauge",
+        "Resolved addresses of the replica that were dead upon last probe",
+        |replica| {
+            replica
+                .failed_addresses
+                .as_ref()
+                .map(|failed_addresses| failed_addresses.len().to_string())
+        },
+    );
+    render_replica(
+        &mut output,
+        states,
+        "script_exit_code",
+        "gauge",
+        "Last exit code of the script replica",
+        |replica| replica.exit_code.map(|exit_code| exit_code.to_string()),
+    );
+    render_replica(
+        &mut output,
+        states,
+        "icmp_loss_ratio",
+        "gauge",
+        "Packet loss ratio of the last ICMP probe of the replica",
+        |replica| {
+            replica
+                .icmp
+                .as_ref()
+                .map(|icmp| (icmp.loss as f64 / 100.0).to_string())
+        },
+    );
+    render_replica(
+        &mut output,
+        states,
+        "icmp_rtt_average_seconds",
+        "gauge",
+        "Average round-trip-time of the last ICMP probe of the replica",
+        |replica| {
+            replica
+                .icmp
+                .as_ref()
+                .and_then(|icmp| icmp.rtt_average.map(milliseconds_to_seconds))
+        },
+    );
+    render_replica(
+        &mut output,
+        states,
+        "icmp_rtt_maximum_seconds",
+        "gauge",
+        "Maximum round-trip-time of the last ICMP probe of the replica",
+        |replica| {
+            replica
+                .icmp
+                .as_ref()
+                .and_then(|icmp| icmp.rtt_maximum.map(milliseconds_to_seconds))
+        },
+    );
+    render_replica(
+        &mut output,
+        states,
+        "icmp_jitter_seconds",
+        "gauge",
+        "Jitter of the last ICMP probe of the replica",
+        |replica| {
+            replica
+                .icmp
+                .as_ref()
+                .and_then(|icmp| icmp.jitter.map(milliseconds_to_seconds))
+        },
+    );
+    render_replica(
+        &mut output,
+        states,
+        "report_attempts_total",
+        "counter",
+        "Status report attempts for the replica",
+        |replica| Some(replica.report_attempts.to_string()),
+    );
+    render_replica(
+        &mut output,
+        states,
+        "report_failures_total",
+        "counter",
+        "Failed status report attempts for the replica",
+        |replica| Some(replica.report_failures.to_string()),
+    );
+    render_replica_target(
+        &mut output,
+        states,
+        "report_target_success",
+        "gauge",
+        "Whether the last status report was accepted by the report target",
+        |target| target.reported.map(|reported| (reported as u8).to_string()),
+    );
+    render_replica_target(
+        &mut output,
+        states,
+        "report_target_attempts_total",
+        "counter",
+        "Status report attempts for the replica on the report target",
+        |target| Some(target.attempts.to_string()),
+    );
+    render_replica_target(
+        &mut output,
+        states,
+        "report_target_failures_total",
+        "counter",
+        "Failed status report attempts for the replica on the report target",
+        |target| Some(target.failures.to_string()),
+    );
+
+    output
+}
+
+fn render_node<F>(
+    output: &mut String,
+    states: &States,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: F,
+) where
+    F: Fn(&StatesServiceNode) -> Option<String>,
+{
+    render_header(output, name, kind, help);
+
+    for (service_id, service) in &states.services {
+        for (node_id, node) in &service.nodes {
+            if let Some(value) = value(node) {
+                let _ = writeln!(
+                    output,
+                    "{}_{}{{service=\"{}\",node=\"{}\"}} {}",
+                    METRICS_PREFIX,
+                    name,
+                    escape(service_id),
+                    escape(node_id),
+                    value
+                );
+            }
+        }
+    }
+}
+
+fn render_replica<F>(
+    output: &mut String,
+    states: &States,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: F,
+) where
+    F: Fn(&StatesServiceNodeReplica) -> Option<String>,
+{
+    render_header(output, name, kind, help);
+
+    for (service_id, service) in &states.services {
+        for (node_id, node) in &service.nodes {
+            for (replica_id, replica) in &node.replicas {
+                if let Some(value) = value(replica) {
+                    let _ = writeln!(
+                        output,
+                        "{}_{}{{service=\"{}\",node=\"{}\",replica=\"{}\"}} {}",
+                        METRICS_PREFIX,
+                        name,
+                        escape(service_id),
+                        escape(node_id),
+                        escape(replica_id),
+                        value
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn render_replica_target<F>(
+    output: &mut String,
+    states: &States,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: F,
+) where
+    F: Fn(&StatesServiceNodeReplicaReportTarget) -> Option<String>,
+{
+    render_header(output, name, kind, help);
+
+    for (service_id, service) in &states.services {
+        for (node_id, node) in &service.nodes {
+            for (replica_id, replica) in &node.replicas {
+                for (target, report_target) in &replica.report_targets {
+                    if let Some(value) = value(report_target) {
+                        let _ = writeln!(
+                            output,
+                            "{}_{}{{service=\"{}\",node=\"{}\",replica=\"{}\",target=\"{}\"}} {}",
+                            METRICS_PREFIX,
+                            name,
+                            escape(service_id),
+                            escape(node_id),
+                            escape(replica_id),
+                            escape(target),
+                            value
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn render_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {}_{} {}", METRICS_PREFIX, name, help);
+    let _ = writeln!(output, "# TYPE {}_{} {}", METRICS_PREFIX, name, kind);
+}
+
+fn escape(value: &str) -> String {
+    // Notice: label values must have backslashes, double quotes and line feeds escaped.
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn milliseconds_to_seconds(milliseconds: u64) -> String {
+    (milliseconds as f64 / 1000.0).to_string()
+}